@@ -0,0 +1,599 @@
+use std::io::Cursor;
+
+use crate::codec::ParameterSets;
+use crate::error::MuxError;
+
+// Rewrites the trailing `moov` box of a finished file in place. Mp4Writer always
+// writes moov last, so resizing it doesn't move any chunk offsets.
+pub(crate) fn patch_moov(buffer: &mut Vec<u8>, patch: impl FnOnce(&mut mp4::MoovBox)) -> mp4::Result<()> {
+    use mp4::{ReadBox, WriteBox};
+
+    let mut cursor = Cursor::new(buffer.as_slice());
+    let mut moov = None;
+    while (cursor.position() as usize) < buffer.len() {
+        let start = cursor.position();
+        let header = mp4::BoxHeader::read(&mut cursor)?;
+        if header.size == 0 {
+            break;
+        }
+        if header.name == mp4::BoxType::MoovBox {
+            moov = Some((start, mp4::MoovBox::read_box(&mut cursor, header.size)?));
+            break;
+        }
+        // Header sizes include the header itself
+        cursor.set_position(start + header.size);
+    }
+
+    let (start, mut moov) = moov.ok_or(mp4::Error::BoxNotFound(mp4::BoxType::MoovBox))?;
+    patch(&mut moov);
+
+    buffer.truncate(start as usize);
+    moov.write_box(buffer)?;
+    Ok(())
+}
+
+// `patch_moov` one track at a time, for moovs with tables long enough that
+// the caller wants to report progress as it goes: `patch` is called once per
+// trak, with a moov holding just the mvhd and that trak. Everything else in
+// the moov is kept as it was.
+pub(crate) fn patch_traks(buffer: &mut Vec<u8>, mut patch: impl FnMut(&mut mp4::MoovBox)) -> Result<(), MuxError> {
+    use mp4::{ReadBox, WriteBox};
+
+    let failed = |source| MuxError::Mp4 { step: "finalize", source };
+    let moov = find_box(buffer, &[b"moov"])?;
+    let mvhd = find_box(buffer, &[b"moov", b"mvhd"])?;
+    let mvhd = &buffer[mvhd.start..mvhd.start + mvhd.size];
+    let end = moov.start + moov.size;
+
+    let mut children = Vec::with_capacity(moov.size);
+    let mut pos = moov.start + child_offset(b"moov");
+    while pos + 8 <= end {
+        let size = read_u32_be(buffer, pos) as usize;
+        if size < 8 || pos + size > end {
+            return Err(MuxError::InvalidConfig(format!("malformed moov child at {}", pos)));
+        }
+        let child = &buffer[pos..pos + size];
+        pos += size;
+        if &child[4..8] != b"trak" {
+            children.extend_from_slice(child);
+            continue;
+        }
+
+        // The crate only parses whole moovs, so give it the smallest valid one
+        let mut single = Vec::with_capacity(8 + mvhd.len() + child.len());
+        write_box(&mut single, b"moov", |single| {
+            single.extend_from_slice(mvhd);
+            single.extend_from_slice(child);
+        });
+        let mut cursor = Cursor::new(single.as_slice());
+        let header = mp4::BoxHeader::read(&mut cursor).map_err(failed)?;
+        let mut parsed = mp4::MoovBox::read_box(&mut cursor, header.size).map_err(failed)?;
+        patch(&mut parsed);
+
+        let mut written = Vec::new();
+        parsed.write_box(&mut written).map_err(failed)?;
+        let trak = find_box(&written, &[b"moov", b"trak"])?;
+        children.extend_from_slice(&written[trak.start..trak.start + trak.size]);
+    }
+
+    let mut patched = Vec::with_capacity(8 + children.len());
+    write_box(&mut patched, b"moov", |patched| patched.extend_from_slice(&children));
+    buffer.splice(moov.start..end, patched);
+    Ok(())
+}
+
+// Bytes from a box's start to its first child box
+fn child_offset(box_type: &[u8; 4]) -> usize {
+    match box_type {
+        // Full box header + entry_count
+        b"stsd" => 16,
+        // Visual sample entry fields
+        b"avc1" | b"avc3" | b"hev1" | b"hvc1" => 86,
+        // Audio sample entry fields
+        b"mp4a" => 36,
+        _ => 8,
+    }
+}
+
+// Located box: header offsets of its ancestors, then its own offset and size
+pub(crate) struct BoxLocation {
+    pub(crate) ancestors: Vec<usize>,
+    pub(crate) start: usize,
+    pub(crate) size: usize,
+}
+
+pub(crate) fn read_u32_be(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+// Finds the box at `path`, matching by type from the top level (first match at each level)
+pub(crate) fn find_box(buffer: &[u8], path: &[&[u8; 4]]) -> Result<BoxLocation, MuxError> {
+    let not_found = || MuxError::InvalidConfig(format!(
+        "box path {:?} not found",
+        path.iter().map(|t| String::from_utf8_lossy(*t).into_owned()).collect::<Vec<_>>()
+    ));
+
+    let mut ancestors = Vec::with_capacity(path.len());
+    let (mut pos, mut end) = (0, buffer.len());
+    for (depth, wanted) in path.iter().enumerate() {
+        let mut found = None;
+        while pos + 8 <= end {
+            let size = read_u32_be(buffer, pos) as usize;
+            // Largesize and to-end-of-file boxes never occur inside a moov we wrote
+            if size < 8 || pos + size > end {
+                return Err(not_found());
+            }
+            if &buffer[pos + 4..pos + 8] == *wanted {
+                found = Some((pos, size));
+                break;
+            }
+            pos += size;
+        }
+        let (start, size) = found.ok_or_else(not_found)?;
+        if depth + 1 == path.len() {
+            return Ok(BoxLocation { ancestors, start, size });
+        }
+        ancestors.push(start);
+        pos = start + child_offset(wanted);
+        end = start + size;
+    }
+    Err(not_found())
+}
+
+// Replaces the box at `path` with `replacement`, fixing up every ancestor's size.
+// Only valid on boxes whose growth can't move chunk data, i.e. inside a trailing moov.
+pub(crate) fn splice_box(buffer: &mut Vec<u8>, path: &[&[u8; 4]], replacement: &[u8]) -> Result<(), MuxError> {
+    let location = find_box(buffer, path)?;
+    let delta = replacement.len() as i64 - location.size as i64;
+    buffer.splice(location.start..location.start + location.size, replacement.iter().copied());
+    for &ancestor in &location.ancestors {
+        let new_size = (read_u32_be(buffer, ancestor) as i64 + delta) as u32;
+        buffer[ancestor..ancestor + 4].copy_from_slice(&new_size.to_be_bytes());
+    }
+    Ok(())
+}
+
+// Appends a box of `box_type` whose payload is produced by `body`
+pub(crate) fn write_box(out: &mut Vec<u8>, box_type: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(box_type);
+    body(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+// Adds `child` as the last child of the box at `path`
+pub(crate) fn append_child(buffer: &mut Vec<u8>, path: &[&[u8; 4]], child: &[u8]) -> Result<(), MuxError> {
+    let location = find_box(buffer, path)?;
+    let end = location.start + location.size;
+    buffer.splice(end..end, child.iter().copied());
+    for &header in location.ancestors.iter().chain(std::iter::once(&location.start)) {
+        let new_size = read_u32_be(buffer, header) + child.len() as u32;
+        buffer[header..header + 4].copy_from_slice(&new_size.to_be_bytes());
+    }
+    Ok(())
+}
+
+// Everything `finalize_moov` adds that the mp4 crate's writer can't express
+pub(crate) struct MoovExtras {
+    pub(crate) parameter_sets: ParameterSets,
+    // (track id, parameter sets) of each additional AVC video track
+    pub(crate) extra_parameter_sets: Vec<(u32, ParameterSets)>,
+    // Raw HEVCDecoderConfigurationRecord for HEVC tracks
+    pub(crate) hvcc: Option<Vec<u8>>,
+    pub(crate) metadata: Metadata,
+    // Clockwise display rotation of the video track in degrees
+    pub(crate) rotation: u16,
+    // Lead-in every track skips on playback, in timescale units
+    pub(crate) start_trim: u64,
+    pub(crate) color: Option<ColorInfo>,
+    pub(crate) mastering_display: Option<MasteringDisplay>,
+    // Parameter sets changed mid-stream and are repeated in-band from then on
+    pub(crate) inband_parameter_sets: bool,
+    // Video composition offsets are made non-negative at `finish` (set by
+    // `add_frame_ordered`), by adding `composition_shift` to each and
+    // starting the video edit that much later
+    pub(crate) shift_composition: bool,
+    pub(crate) composition_shift: u64,
+    // Text track holding the chapters, which the primary video references
+    pub(crate) chapter_track: Option<u32>,
+}
+
+// nclx colour description, using the ITU-T H.273 code points
+#[derive(Clone, Copy)]
+pub(crate) struct ColorInfo {
+    pub(crate) primaries: u16,
+    pub(crate) transfer: u16,
+    pub(crate) matrix: u16,
+    pub(crate) full_range: bool,
+}
+
+// SMPTE ST 2086 mastering display colour volume for HDR content
+#[derive(Clone, Copy)]
+pub(crate) struct MasteringDisplay {
+    // (x, y) chromaticity pairs in 0.00002 units, in G, B, R order
+    pub(crate) primaries: [(u16, u16); 3],
+    pub(crate) white_point: (u16, u16),
+    // In 0.0001 cd/m^2 units
+    pub(crate) max_luminance: u32,
+    pub(crate) min_luminance: u32,
+}
+
+// Optional descriptive metadata written to the moov
+#[derive(Clone, Default)]
+pub(crate) struct Metadata {
+    pub(crate) title: Option<String>,
+    // "Made with" tag, i.e. the encoding tool
+    pub(crate) encoder: Option<String>,
+    // Seconds since the Unix epoch
+    pub(crate) creation_time: Option<u64>,
+    // Cover art, JPEG or PNG
+    pub(crate) poster: Option<Vec<u8>>,
+}
+
+// ilst data types for the poster's image format, sniffed from its signature
+pub(crate) fn poster_data_type(image: &[u8]) -> Option<u32> {
+    if image.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(13)
+    } else if image.starts_with(b"\x89PNG") {
+        Some(14)
+    } else {
+        None
+    }
+}
+
+// Seconds between the mp4 epoch (1904-01-01) and the Unix epoch
+const MP4_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+// Applies the fixups the mp4 crate can't express to a finished moov
+pub(crate) fn finalize_moov(buffer: &mut Vec<u8>, extras: &MoovExtras) -> Result<(), MuxError> {
+    // The primary video track is always track 1
+    let primary = extras.hvcc.is_none().then_some((1, &extras.parameter_sets));
+    let restore_sets: Vec<(u32, &ParameterSets)> = primary
+        .into_iter()
+        .chain(extras.extra_parameter_sets.iter().map(|(track_id, sets)| (*track_id, sets)))
+        .filter(|(_, sets)| sets.sps.len() > 1 || sets.pps.len() > 1)
+        .collect();
+    let creation_time = extras.metadata.creation_time.map(|t| t + MP4_EPOCH_OFFSET);
+
+    let has_edits = extras.start_trim > 0 || extras.composition_shift > 0;
+    if !restore_sets.is_empty() || creation_time.is_some() || has_edits {
+        patch_moov(buffer, |moov| {
+            if let Some(time) = creation_time {
+                // Version 1 headers carry 64-bit times
+                let version = if time > u32::MAX as u64 { 1 } else { moov.mvhd.version };
+                moov.mvhd.version = version;
+                moov.mvhd.creation_time = time;
+                moov.mvhd.modification_time = time;
+                for trak in moov.traks.iter_mut() {
+                    trak.tkhd.version = trak.tkhd.version.max(version);
+                    trak.tkhd.creation_time = time;
+                    trak.tkhd.modification_time = time;
+                    trak.mdia.mdhd.version = trak.mdia.mdhd.version.max(version);
+                    trak.mdia.mdhd.creation_time = time;
+                    trak.mdia.mdhd.modification_time = time;
+                }
+            }
+
+            // Restore the parameter sets the mp4 crate's AvcConfig had no room for
+            for (track_id, sets) in &restore_sets {
+                for trak in moov.traks.iter_mut().filter(|trak| trak.tkhd.track_id == *track_id) {
+                    if let Some(avc1) = trak.mdia.minf.stbl.stsd.avc1.as_mut() {
+                        avc1.avcc.sequence_parameter_sets =
+                            sets.sps.iter().map(|sps| sps.as_slice().into()).collect();
+                        avc1.avcc.picture_parameter_sets =
+                            sets.pps.iter().map(|pps| pps.as_slice().into()).collect();
+                    }
+                }
+            }
+
+            if has_edits {
+                write_start_trim(moov, extras.start_trim, extras.composition_shift);
+            }
+        }).map_err(|source| MuxError::Mp4 { step: "finalize", source })?;
+    }
+    if let Some(hvcc) = extras.hvcc.as_deref() {
+        write_hvcc(buffer, hvcc)?;
+    }
+    if extras.rotation != 0 {
+        write_rotation(buffer, extras.rotation)?;
+    }
+    write_color(buffer, extras)?;
+    if extras.inband_parameter_sets {
+        allow_inband_parameter_sets(buffer)?;
+    }
+    if let Some(udta) = metadata_udta(&extras.metadata) {
+        append_child(buffer, &[b"moov"], &udta)?;
+    }
+    // Last, since the mp4 crate would drop these boxes when parsing the moov
+    if let Some(track_id) = extras.chapter_track {
+        write_chapter_refs(buffer, track_id)?;
+    }
+    Ok(())
+}
+
+// Links the chapter text track to the primary video with a `tref`/`chap`,
+// without which QuickTime doesn't list the chapters, and disables it so
+// players don't also show the titles as subtitles
+fn write_chapter_refs(buffer: &mut Vec<u8>, chapter_track: u32) -> Result<(), MuxError> {
+    const DINF_PATH: [&[u8; 4]; 4] = [b"trak", b"mdia", b"minf", b"dinf"];
+
+    edit_trak(buffer, chapter_track, |trak| {
+        let tkhd = find_box(trak, &[b"trak", b"tkhd"])?;
+        // Flags: in the movie, but not enabled
+        trak[tkhd.start + 9..tkhd.start + 12].copy_from_slice(&[0, 0, 2]);
+
+        // The mp4 crate writes no media header for text tracks; they take a null one
+        let mut nmhd = Vec::new();
+        write_box(&mut nmhd, b"nmhd", |nmhd| nmhd.extend_from_slice(&[0; 4])); // version + flags
+        let dinf = find_box(trak, &DINF_PATH)?;
+        nmhd.extend_from_slice(&trak[dinf.start..dinf.start + dinf.size]);
+        splice_box(trak, &DINF_PATH, &nmhd)
+    })?;
+    // The primary video track is always track 1
+    edit_trak(buffer, 1, |trak| {
+        let tkhd = find_box(trak, &[b"trak", b"tkhd"])?;
+        let mut with_tref = trak[tkhd.start..tkhd.start + tkhd.size].to_vec();
+        write_box(&mut with_tref, b"tref", |tref| {
+            write_box(tref, b"chap", |chap| chap.extend_from_slice(&chapter_track.to_be_bytes()));
+        });
+        splice_box(trak, &[b"trak", b"tkhd"], &with_tref)
+    })
+}
+
+// Runs `edit` on a copy of the moov's trak for `track_id`, so the box helpers'
+// paths start at b"trak", and puts the result back in its place
+fn edit_trak(
+    buffer: &mut Vec<u8>,
+    track_id: u32,
+    edit: impl FnOnce(&mut Vec<u8>) -> Result<(), MuxError>,
+) -> Result<(), MuxError> {
+    let moov = find_box(buffer, &[b"moov"])?;
+    let end = moov.start + moov.size;
+    let mut pos = moov.start + child_offset(b"moov");
+    while pos + 8 <= end {
+        let size = read_u32_be(buffer, pos) as usize;
+        if size < 8 || pos + size > end {
+            break;
+        }
+        if &buffer[pos + 4..pos + 8] == b"trak" {
+            let mut trak = buffer[pos..pos + size].to_vec();
+            let tkhd = find_box(&trak, &[b"trak", b"tkhd"])?;
+            // The id follows the creation and modification times, 64-bit in version 1
+            let id_at = tkhd.start + if trak[tkhd.start + 8] == 1 { 28 } else { 20 };
+            if read_u32_be(&trak, id_at) == track_id {
+                edit(&mut trak)?;
+                let moov_size = (moov.size + trak.len()) as u32 - size as u32;
+                buffer.splice(pos..pos + size, trak);
+                buffer[moov.start..moov.start + 4].copy_from_slice(&moov_size.to_be_bytes());
+                return Ok(());
+            }
+        }
+        pos += size;
+    }
+    Err(MuxError::InvalidConfig(format!("no trak with track id {}", track_id)))
+}
+
+// Gives every track a single edit that starts `trim` units into its media, so
+// players skip the lead-in while the samples (and the first keyframe) stay.
+// The video edit starts `video_shift` further in, undoing the delay shifted
+// composition offsets add. Assumes the movie and media timescales match, as
+// they do in files we write.
+fn write_start_trim(moov: &mut mp4::MoovBox, trim: u64, video_shift: u64) {
+    let mut movie_duration = 0;
+    for trak in moov.traks.iter_mut() {
+        let media_duration = trak.mdia.mdhd.duration;
+        // Never trim past the end of the track
+        let trim = trim.min(media_duration);
+        let segment_duration = media_duration - trim;
+        let shift = if trak.tkhd.track_id == 1 { video_shift } else { 0 };
+
+        let mut edts = trak.edts.take().unwrap_or_default();
+        let mut elst = edts.elst.take().unwrap_or_default();
+        elst.version = if media_duration > u32::MAX as u64 { 1 } else { 0 };
+        elst.entries.clear();
+        elst.entries.push(Default::default());
+        let entry = elst.entries.last_mut().unwrap();
+        entry.segment_duration = segment_duration;
+        entry.media_time = trim + shift;
+        entry.media_rate = 1;
+        edts.elst = Some(elst);
+        trak.edts = Some(edts);
+
+        // A track lasts as long as its edits
+        trak.tkhd.duration = segment_duration;
+        movie_duration = movie_duration.max(segment_duration);
+    }
+    moov.mvhd.duration = movie_duration;
+}
+
+// Sets the video tkhd matrix so players rotate the picture clockwise by
+// `degrees`, translated back into the positive quadrant like other muxers do
+pub(crate) fn write_rotation(buffer: &mut [u8], degrees: u16) -> Result<(), MuxError> {
+    let tkhd = find_box(buffer, &[b"moov", b"trak", b"tkhd"])?;
+    // Version 1 has 64-bit creation/modification times and duration
+    let matrix_at = tkhd.start + if buffer[tkhd.start + 8] == 1 { 60 } else { 48 };
+    // Width and height (16.16) follow the matrix
+    let width = read_u32_be(buffer, matrix_at + 36) >> 16;
+    let height = read_u32_be(buffer, matrix_at + 40) >> 16;
+
+    let matrix = rotation_matrix(degrees, width, height)?;
+    for (i, value) in matrix.iter().enumerate() {
+        buffer[matrix_at + i * 4..matrix_at + i * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    Ok(())
+}
+
+// The tkhd matrix for `degrees` on a `width` x `height` track. The
+// translation is signed 16.16, so a rotated side of 32768 px or more can't be
+// expressed and is refused.
+pub(crate) fn rotation_matrix(degrees: u16, width: u32, height: u32) -> Result<[i32; 9], MuxError> {
+    // 16.16 fixed point, except the last column which is 2.30
+    const ONE: i32 = 0x0001_0000;
+    const W: i32 = 0x4000_0000;
+    let fixed = |px: u32| {
+        i32::try_from(px as i64 * ONE as i64).map_err(|_| MuxError::InvalidConfig(format!(
+            "rotation needs dimensions below 32768, got {}x{}", width, height
+        )))
+    };
+
+    Ok(match degrees {
+        0 => [ONE, 0, 0, 0, ONE, 0, 0, 0, W],
+        90 => [0, ONE, 0, -ONE, 0, 0, fixed(height)?, 0, W],
+        180 => [-ONE, 0, 0, 0, -ONE, 0, fixed(width)?, fixed(height)?, W],
+        270 => [0, -ONE, 0, ONE, 0, 0, 0, fixed(width)?, W],
+        _ => return Err(unsupported_rotation(degrees)),
+    })
+}
+
+// Adds (or replaces) the colr and mdcv boxes of the video sample entry
+pub(crate) fn write_color(buffer: &mut Vec<u8>, extras: &MoovExtras) -> Result<(), MuxError> {
+    if let Some(color) = extras.color {
+        let mut colr = Vec::new();
+        write_box(&mut colr, b"colr", |colr| {
+            colr.extend_from_slice(b"nclx");
+            colr.extend_from_slice(&color.primaries.to_be_bytes());
+            colr.extend_from_slice(&color.transfer.to_be_bytes());
+            colr.extend_from_slice(&color.matrix.to_be_bytes());
+            colr.push(if color.full_range { 0x80 } else { 0 });
+        });
+        put_video_entry_child(buffer, b"colr", &colr)?;
+    }
+    if let Some(display) = extras.mastering_display {
+        let mut mdcv = Vec::new();
+        write_box(&mut mdcv, b"mdcv", |mdcv| {
+            for (x, y) in display.primaries.iter().chain(std::iter::once(&display.white_point)) {
+                mdcv.extend_from_slice(&x.to_be_bytes());
+                mdcv.extend_from_slice(&y.to_be_bytes());
+            }
+            mdcv.extend_from_slice(&display.max_luminance.to_be_bytes());
+            mdcv.extend_from_slice(&display.min_luminance.to_be_bytes());
+        });
+        put_video_entry_child(buffer, b"mdcv", &mdcv)?;
+    }
+    Ok(())
+}
+
+// Replaces the video sample entry's `box_type` child with `child`, or appends it
+fn put_video_entry_child(buffer: &mut Vec<u8>, box_type: &[u8; 4], child: &[u8]) -> Result<(), MuxError> {
+    let stsd = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"];
+    let entry = [b"avc1", b"avc3", b"hvc1", b"hev1"]
+        .into_iter()
+        .find(|entry| find_box(buffer, &[&stsd[..], &[*entry]].concat()).is_ok())
+        .ok_or_else(|| MuxError::InvalidConfig("no video sample entry to describe".into()))?;
+    let mut path = stsd.to_vec();
+    path.push(entry);
+
+    let mut child_path = path.clone();
+    child_path.push(box_type);
+    if find_box(buffer, &child_path).is_ok() {
+        splice_box(buffer, &child_path, child)
+    } else {
+        append_child(buffer, &path, child)
+    }
+}
+
+// Switches the video sample entry to the variant that lets samples carry parameter
+// sets overriding the decoder config (avc1 -> avc3, hvc1 -> hev1). Same layout,
+// so only the type changes.
+pub(crate) fn allow_inband_parameter_sets(buffer: &mut [u8]) -> Result<(), MuxError> {
+    let stsd = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"];
+    for (from, to) in [(b"avc1", b"avc3"), (b"hvc1", b"hev1")] {
+        if let Ok(entry) = find_box(buffer, &[&stsd[..], &[from]].concat()) {
+            buffer[entry.start + 4..entry.start + 8].copy_from_slice(to);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn unsupported_rotation(degrees: u16) -> MuxError {
+    MuxError::InvalidConfig(format!("rotation must be 0, 90, 180 or 270 degrees, got {}", degrees))
+}
+
+// iTunes-style udta/meta/ilst carrying the title, encoder, date and cover art,
+// which is what media info tools and players read. None if there's nothing to write.
+fn metadata_udta(metadata: &Metadata) -> Option<Vec<u8>> {
+    // UTF-8 text unless the item says otherwise
+    const TEXT: u32 = 1;
+    let date = metadata.creation_time.map(iso8601_date);
+    let poster = metadata.poster.as_deref()
+        .and_then(|image| Some((poster_data_type(image)?, image)));
+    let items = [
+        (b"\xa9nam", metadata.title.as_deref().map(|v| (TEXT, v.as_bytes()))),
+        (b"\xa9too", metadata.encoder.as_deref().map(|v| (TEXT, v.as_bytes()))),
+        (b"\xa9day", date.as_deref().map(|v| (TEXT, v.as_bytes()))),
+        (b"covr", poster),
+    ];
+    if items.iter().all(|(_, value)| value.is_none()) {
+        return None;
+    }
+
+    let mut udta = Vec::new();
+    write_box(&mut udta, b"udta", |udta| {
+        write_box(udta, b"meta", |meta| {
+            meta.extend_from_slice(&[0, 0, 0, 0]); // version + flags
+            write_box(meta, b"hdlr", |hdlr| {
+                hdlr.extend_from_slice(&[0; 8]); // version + flags, pre_defined
+                hdlr.extend_from_slice(b"mdir");
+                hdlr.extend_from_slice(b"appl");
+                hdlr.extend_from_slice(&[0; 9]); // reserved, empty name
+            });
+            write_box(meta, b"ilst", |ilst| {
+                for (key, value) in items {
+                    let Some((data_type, value)) = value else { continue };
+                    write_box(ilst, key, |item| {
+                        write_box(item, b"data", |data| {
+                            data.extend_from_slice(&data_type.to_be_bytes());
+                            data.extend_from_slice(&[0; 4]); // locale
+                            data.extend_from_slice(value);
+                        });
+                    });
+                }
+            });
+        });
+    });
+    Some(udta)
+}
+
+// "YYYY-MM-DDTHH:MM:SSZ" for a Unix timestamp
+fn iso8601_date(unix_seconds: u64) -> String {
+    let days = (unix_seconds / 86_400) as i64;
+    let secs = unix_seconds % 86_400;
+
+    // Days-to-civil conversion from Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs / 3_600, secs / 60 % 60, secs % 60
+    )
+}
+
+// Rewrites the stub `hev1`/`hvcC` the mp4 crate emits into a real `hvc1` entry
+pub(crate) fn write_hvcc(buffer: &mut Vec<u8>, hvcc: &[u8]) -> Result<(), MuxError> {
+    const HEV1_PATH: [&[u8; 4]; 7] = [b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd", b"hev1"];
+
+    let mut hvcc_box = Vec::with_capacity(hvcc.len() + 8);
+    hvcc_box.extend_from_slice(&(hvcc.len() as u32 + 8).to_be_bytes());
+    hvcc_box.extend_from_slice(b"hvcC");
+    hvcc_box.extend_from_slice(hvcc);
+
+    let mut hvcc_path = HEV1_PATH.to_vec();
+    hvcc_path.push(b"hvcC");
+    splice_box(buffer, &hvcc_path, &hvcc_box)?;
+
+    // hvc1 keeps parameter sets out of band, which is what QuickTime requires.
+    // Same layout as hev1, so only the type changes.
+    let entry = find_box(buffer, &HEV1_PATH)?;
+    buffer[entry.start + 4..entry.start + 8].copy_from_slice(b"hvc1");
+    Ok(())
+}
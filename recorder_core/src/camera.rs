@@ -0,0 +1,2023 @@
+use serde::{Serialize, Deserialize};
+use crate::error::CameraError;
+use crate::log;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Rect {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> Rect {
+        Rect { x, y, width, height }
+    }
+}
+
+// Physics constants for smooth camera movement
+// Higher stiffness for more responsive, immediate tracking
+const CAMERA_STIFFNESS: f64 = 150.0; // Increased from 100 for faster response
+const CAMERA_DAMPING: f64 = 2.0 * 12.247; // Critical damping: 2 * sqrt(150) -> 2 * 12.247 = 24.49
+const CAMERA_MASS: f64 = 1.0;
+// Floors so a zero from JS can't blow up the acceleration division
+const MIN_STIFFNESS: f64 = 0.001;
+const MIN_MASS: f64 = 0.001;
+
+// Default zoom constraints (MIN_ZOOM is also the hard floor: below 1.0 the view would leave the source)
+const MIN_ZOOM: f64 = 1.0;  // 1.0 = no zoom (full view)
+const MAX_ZOOM: f64 = 2.5;  // 2.5 = maximum zoom in
+const ZOOM_TAU: f64 = 1.0 / 6.0; // Seconds; short for immediate feedback
+
+// Physics integration step limits
+const MAX_PHYSICS_STEP: f64 = 1.0 / 60.0; // Longer frames are split into sub-steps
+const MAX_PHYSICS_SUBSTEPS: u32 = 60;     // Stalls beyond 1s only simulate 1s; the spring has settled by then
+const FIXED_STEP: f64 = 1.0 / 120.0;      // Simulation step of `update_fixed`
+
+// Zoom needed for a source element `element_width` px wide to fill
+// `desired_fraction` of the output, where `view_width` is the source width
+// visible at zoom 1.0. Clamped to the default zoom range; a rig with its own
+// `set_zoom_limits` should use `CameraRig::zoom_for_onscreen_size`.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub fn zoom_for_onscreen_size(element_width: f64, desired_fraction: f64, view_width: f64) -> f64 {
+    onscreen_zoom(element_width, desired_fraction, view_width, MIN_ZOOM, MAX_ZOOM)
+}
+
+fn onscreen_zoom(element_width: f64, desired_fraction: f64, view_width: f64, min: f64, max: f64) -> f64 {
+    if !(element_width > 0.0 && desired_fraction > 0.0 && view_width > 0.0) {
+        return min;
+    }
+    // At zoom z the output shows view_width / z source px, so the element
+    // covers element_width * z / view_width of the frame
+    let zoom = desired_fraction * view_width / element_width;
+    if zoom.is_finite() {
+        zoom.clamp(min, max)
+    } else {
+        min
+    }
+}
+
+// Progress curves for scripted camera moves
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseInOut,
+    EaseOut,
+}
+
+impl Easing {
+    // Maps linear progress in [0, 1] onto the curve
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            // Smoothstep: zero velocity at both ends
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            // Quadratic: full speed at the start, zero at the end
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+        }
+    }
+}
+
+// Ready-made spring feels, so callers don't have to pick physics constants
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SpringPreset {
+    // Slow and slightly overdamped: no overshoot, good for documentation
+    Gentle,
+    // Fast and critically damped
+    Snappy,
+    // Underdamped: overshoots a little and settles
+    Bouncy,
+}
+
+impl SpringPreset {
+    // (stiffness, damping ratio)
+    fn params(self) -> (f64, f64) {
+        match self {
+            SpringPreset::Gentle => (60.0, 1.2),
+            SpringPreset::Snappy => (300.0, 1.0),
+            SpringPreset::Bouncy => (200.0, 0.45),
+        }
+    }
+}
+
+// One recorded camera pose; `t` is seconds since recording started
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct PathSample {
+    pub t: f64,
+    pub x: f64,
+    pub y: f64,
+    pub zoom: f64,
+}
+
+// Centered view: the source point at the output's center and the scale
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct ViewState {
+    pub x: f64,
+    pub y: f64,
+    pub zoom: f64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct Velocity {
+    pub vx: f64,
+    pub vy: f64,
+    pub speed: f64,
+}
+
+// Range the view center can reach at the current zoom, after the bounds
+// margin, and the size of the source area that view covers
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct ViewBounds {
+    pub min_x: f64,
+    pub max_x: f64,
+    pub min_y: f64,
+    pub max_y: f64,
+    pub view_width: f64,
+    pub view_height: f64,
+}
+
+// A point of interest for `update_multi`; heavier points pull harder
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct TargetPoint {
+    pub x: f64,
+    pub y: f64,
+    pub weight: f64,
+}
+
+// The eight numeric `drawImage(source, sx, sy, sw, sh, dx, dy, dw, dh)` arguments
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct DrawParams {
+    pub sx: f64,
+    pub sy: f64,
+    pub sw: f64,
+    pub sh: f64,
+    pub dx: f64,
+    pub dy: f64,
+    pub dw: f64,
+    pub dh: f64,
+}
+
+// Opt-in trajectory log for baking the camera motion into an editor
+struct PathRecorder {
+    recording: bool,
+    elapsed: f64,
+    samples: Vec<PathSample>,
+    max_samples: usize,
+    // Only every `stride`-th update is kept; doubles each time the cap is hit
+    stride: u32,
+    skipped: u32,
+}
+
+// 10 minutes at 60 updates/sec before decimation kicks in
+const DEFAULT_PATH_MAX_SAMPLES: usize = 36_000;
+
+impl Default for PathRecorder {
+    fn default() -> Self {
+        PathRecorder {
+            recording: false,
+            elapsed: 0.0,
+            samples: Vec::new(),
+            max_samples: DEFAULT_PATH_MAX_SAMPLES,
+            stride: 1,
+            skipped: 0,
+        }
+    }
+}
+
+impl PathRecorder {
+    fn record(&mut self, dt: f64, x: f64, y: f64, zoom: f64) {
+        if !self.recording {
+            return;
+        }
+        self.elapsed += dt.max(0.0);
+        self.skipped += 1;
+        if self.skipped < self.stride {
+            return;
+        }
+        self.skipped = 0;
+
+        // Halve the resolution instead of growing without bound, so a long
+        // recording stays evenly sampled from start to finish
+        if self.samples.len() >= self.max_samples {
+            let mut keep = false;
+            self.samples.retain(|_| {
+                keep = !keep;
+                keep
+            });
+            self.stride *= 2;
+        }
+        self.samples.push(PathSample { t: self.elapsed, x, y, zoom });
+    }
+}
+
+// Activity-driven zoom: close in on small, concentrated target movement and
+// pull back out when the target travels across the screen
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct AutoZoom {
+    // Seconds of target history to measure
+    window: f64,
+    // RMS spread (source px) at or below which the detail zoom applies
+    detail_spread: f64,
+    // RMS spread at or above which the view zooms fully out
+    travel_spread: f64,
+    detail_zoom: f64,
+    elapsed: f64,
+    history: std::collections::VecDeque<(f64, f64, f64)>,
+}
+
+impl AutoZoom {
+    // Records the target and returns the zoom its recent spread calls for
+    fn observe(&mut self, x: f64, y: f64, dt: f64) -> f64 {
+        self.elapsed += dt.max(0.0);
+        self.history.push_back((self.elapsed, x, y));
+        while self.history.front().is_some_and(|&(t, _, _)| t < self.elapsed - self.window) {
+            self.history.pop_front();
+        }
+
+        let n = self.history.len() as f64;
+        let (sum_x, sum_y) = self.history.iter().fold((0.0, 0.0), |(sx, sy), &(_, x, y)| (sx + x, sy + y));
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let variance = self.history.iter()
+            .map(|&(_, x, y)| (x - mean_x).powi(2) + (y - mean_y).powi(2))
+            .sum::<f64>() / n;
+        let spread = variance.sqrt();
+
+        // Linear blend from the detail zoom down to 1.0 between the two thresholds
+        let t = ((spread - self.detail_spread) / (self.travel_spread - self.detail_spread)).clamp(0.0, 1.0);
+        self.detail_zoom + (1.0 - self.detail_zoom) * t
+    }
+}
+
+// Time bookkeeping for `update_fixed`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+struct FixedClock {
+    // Real time not simulated yet, always under one FIXED_STEP
+    accumulator: f64,
+    // (x, y, zoom) before the latest fixed step
+    prev: Option<(f64, f64, f64)>,
+    // What the getters show: `prev` blended towards the current state by
+    // the leftover time. None after a plain `update`.
+    pose: Option<(f64, f64, f64)>,
+}
+
+// An in-flight `tween_to` move
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+struct Tween {
+    from: (f64, f64, f64),
+    to: (f64, f64, f64),
+    duration: f64,
+    elapsed: f64,
+    easing: Easing,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Serialize, Deserialize)]
+pub struct CameraRig {
+    x: f64,
+    y: f64,
+    vx: f64,
+    vy: f64,
+    zoom_level: f64,
+    target_zoom: f64,
+    min_zoom: f64,
+    max_zoom: f64,
+    // Time constant (seconds) zoom_level approaches target_zoom with; 0 jumps
+    zoom_tau: f64,
+    // Per-axis spring constants, so vertical motion can be calmer than horizontal
+    stiffness_x: f64,
+    stiffness_y: f64,
+    damping_x: f64,
+    damping_y: f64,
+    mass: f64,
+    src_width: f64,
+    src_height: f64,
+    // First-order low-pass on the incoming target (0 = disabled)
+    target_lowpass_hz: f64,
+    filtered_target: Option<(f64, f64)>,
+    // Targets closer than this (source px) to the camera exert no pull
+    dead_zone: f64,
+    // Cap on the camera's speed in source px/sec (0 = unlimited, which unlike
+    // infinity survives a JSON round trip of the state)
+    max_speed: f64,
+    // Seconds of target velocity to lead by (0 = aim at the raw target)
+    lookahead: f64,
+    prev_target: Option<(f64, f64)>,
+    // Clamped point the spring pulled towards on the latest step
+    aim: Option<(f64, f64)>,
+    // When false, `update` holds the camera still instead of chasing the target
+    following: bool,
+    // Whether an update has run since the last reset, ending the window for `prime`
+    updated: bool,
+    // Keeps the view this many source px away from the source edges
+    bounds_margin: f64,
+    // Padding kept around the important points by `update_multi`'s zoom, if on
+    multi_zoom_padding: Option<f64>,
+    // Width / height the crop rect is shaped to (0 = the source's own aspect)
+    output_aspect: f64,
+    // Source px an axis is held at, ignoring the target, while locked
+    lock_x: Option<f64>,
+    lock_y: Option<f64>,
+    // Zoom about the follow target instead of the view center
+    zoom_focus_follow: bool,
+    // Drives target_zoom from target activity while set
+    auto_zoom: Option<AutoZoom>,
+    // Scripted move that overrides the spring until it completes
+    tween: Option<Tween>,
+    // Recordings are session data, not part of the saved camera state
+    #[serde(skip)]
+    path: PathRecorder,
+    // Saved with the rest so a restored rig's next update matches the original's
+    fixed: FixedClock,
+    // Time constant (seconds) of the output smoothing; 0 is off
+    smoothing: f64,
+    // Smoothed (x, y) shown by the getters while smoothing is on
+    smoothed: Option<(f64, f64)>,
+    // Whether a NaN/Inf input has been reported yet
+    #[serde(skip)]
+    warned_non_finite: bool,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl CameraRig {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(src_width: f64, src_height: f64) -> CameraRig {
+        CameraRig {
+            x: src_width / 2.0,
+            y: src_height / 2.0,
+            vx: 0.0,
+            vy: 0.0,
+            zoom_level: 1.0,
+            target_zoom: 1.0,
+            min_zoom: MIN_ZOOM,
+            max_zoom: MAX_ZOOM,
+            zoom_tau: ZOOM_TAU,
+            stiffness_x: CAMERA_STIFFNESS,
+            stiffness_y: CAMERA_STIFFNESS,
+            damping_x: CAMERA_DAMPING,
+            damping_y: CAMERA_DAMPING,
+            mass: CAMERA_MASS,
+            src_width,
+            src_height,
+            target_lowpass_hz: 0.0,
+            filtered_target: None,
+            dead_zone: 0.0,
+            max_speed: 0.0,
+            lookahead: 0.0,
+            prev_target: None,
+            aim: None,
+            following: true,
+            updated: false,
+            bounds_margin: 0.0,
+            multi_zoom_padding: None,
+            output_aspect: 0.0,
+            lock_x: None,
+            lock_y: None,
+            zoom_focus_follow: false,
+            auto_zoom: None,
+            tween: None,
+            path: PathRecorder::default(),
+            fixed: FixedClock::default(),
+            smoothing: 0.0,
+            smoothed: None,
+            warned_non_finite: false,
+        }
+    }
+
+    // Starts already framed on (x, y, zoom), clamped like `reset_to`, so a
+    // recording that opens zoomed in doesn't pan there over its first frames
+    pub fn new_at(src_width: f64, src_height: f64, x: f64, y: f64, zoom: f64) -> CameraRig {
+        let mut rig = CameraRig::new(src_width, src_height);
+        rig.reset_to(x, y, zoom);
+        rig
+    }
+
+    pub fn reset(&mut self) {
+        self.x = self.src_width / 2.0;
+        self.y = self.src_height / 2.0;
+        self.vx = 0.0;
+        self.vy = 0.0;
+        self.zoom_level = self.min_zoom;
+        self.target_zoom = self.min_zoom;
+        self.filtered_target = None;
+        self.prev_target = None;
+        self.aim = None;
+        self.updated = false;
+        self.tween = None;
+        self.fixed = FixedClock::default();
+        self.smoothed = None;
+    }
+
+    // Restore a saved camera; the position is clamped to what's reachable at `zoom`
+    pub fn reset_to(&mut self, x: f64, y: f64, zoom: f64) {
+        if !self.accepts_finite("reset_to", &[x, y, zoom]) {
+            return;
+        }
+        self.reset();
+        let zoom = zoom.clamp(self.min_zoom, self.max_zoom);
+        let (min_x, max_x, min_y, max_y) = self.bounds(zoom);
+        self.x = x.clamp(min_x, max_x);
+        self.y = y.clamp(min_y, max_y);
+        self.zoom_level = zoom;
+        self.target_zoom = zoom;
+    }
+
+    // Hard cut to the (clamped) target for scene changes: no pan, no velocity,
+    // and the target filters restart from here. Unlike `reset` the zoom and
+    // settings are kept; the next `update` follows from the new position.
+    pub fn snap_to(&mut self, target_x: f64, target_y: f64) {
+        if !self.accepts_finite("snap_to", &[target_x, target_y]) {
+            return;
+        }
+        let (min_x, max_x, min_y, max_y) = self.bounds(self.zoom_level);
+        self.x = target_x.clamp(min_x, max_x);
+        self.y = target_y.clamp(min_y, max_y);
+        self.vx = 0.0;
+        self.vy = 0.0;
+        self.filtered_target = None;
+        self.prev_target = None;
+        self.aim = Some((self.x, self.y));
+        self.tween = None;
+        self.fixed = FixedClock::default();
+        self.smoothed = None;
+    }
+
+    // Pre-roll for follow recordings: start on the subject's first (clamped)
+    // position, at rest as if the camera had already converged there, so the
+    // first frame doesn't lurch away from center. Only before the first
+    // update (or after a reset); later calls are ignored with a warning.
+    pub fn prime(&mut self, target_x: f64, target_y: f64) {
+        if self.updated {
+            log::warn("CameraRig: prime called after updates began; ignoring it (use snap_to to cut)");
+            return;
+        }
+        self.snap_to(self.lock_x.unwrap_or(target_x), self.lock_y.unwrap_or(target_y));
+    }
+
+    // Jump straight to `zoom` instead of easing there, re-clamping the position
+    pub fn snap_zoom(&mut self, zoom: f64) {
+        if !self.accepts_finite("snap_zoom", &[zoom]) {
+            return;
+        }
+        let zoom = zoom.clamp(self.min_zoom, self.max_zoom);
+        self.zoom_level = zoom;
+        self.target_zoom = zoom;
+        self.tween = None;
+        self.fixed = FixedClock::default();
+        self.smoothed = None;
+        let (min_x, max_x, min_y, max_y) = self.bounds(zoom);
+        self.x = self.x.clamp(min_x, max_x);
+        self.y = self.y.clamp(min_y, max_y);
+    }
+
+    // Same spring on both axes
+    pub fn set_spring(&mut self, stiffness: f64, damping: f64, mass: f64) {
+        if !self.accepts_finite("set_spring", &[stiffness, damping, mass]) {
+            return;
+        }
+        self.mass = mass.max(MIN_MASS);
+        self.set_axis_spring(stiffness, damping, stiffness, damping);
+    }
+
+    // Independent spring constants per axis; the mass is shared
+    pub fn set_axis_spring(&mut self, stiffness_x: f64, damping_x: f64, stiffness_y: f64, damping_y: f64) {
+        // An infinite force turns the spring state into NaN on the next step
+        if !self.accepts_finite("set_axis_spring", &[stiffness_x, damping_x, stiffness_y, damping_y]) {
+            return;
+        }
+        self.stiffness_x = stiffness_x.max(MIN_STIFFNESS);
+        self.stiffness_y = stiffness_y.max(MIN_STIFFNESS);
+        self.damping_x = damping_x.max(0.0);
+        self.damping_y = damping_y.max(0.0);
+    }
+
+    // Keeps the current mass and derives damping as 2 * sqrt(k * m) on both axes
+    pub fn set_critically_damped(&mut self, stiffness: f64) {
+        if !self.accepts_finite("set_critically_damped", &[stiffness]) {
+            return;
+        }
+        let stiffness = stiffness.max(MIN_STIFFNESS);
+        let damping = 2.0 * (stiffness * self.mass).sqrt();
+        self.set_axis_spring(stiffness, damping, stiffness, damping);
+    }
+
+    // Damping relative to critical (1.0), keeping each axis's stiffness and
+    // the mass: below 1.0 overshoots and settles, above 1.0 creeps in
+    pub fn set_damping_ratio(&mut self, ratio: f64) {
+        if !self.accepts_finite("set_damping_ratio", &[ratio]) {
+            return;
+        }
+        let ratio = ratio.max(0.0);
+        self.damping_x = ratio * 2.0 * (self.stiffness_x * self.mass).sqrt();
+        self.damping_y = ratio * 2.0 * (self.stiffness_y * self.mass).sqrt();
+    }
+
+    pub fn set_spring_preset(&mut self, preset: SpringPreset) {
+        let (stiffness, ratio) = preset.params();
+        self.set_axis_spring(stiffness, 0.0, stiffness, 0.0);
+        self.set_damping_ratio(ratio);
+    }
+
+    pub fn set_target_zoom(&mut self, zoom: f64) {
+        // NaN would survive the clamp and reach the bounds math in `update`
+        if !self.accepts_finite("set_target_zoom", &[zoom]) {
+            return;
+        }
+        self.target_zoom = zoom.clamp(self.min_zoom, self.max_zoom);
+    }
+
+    // Pass (1.0, 1.0) to lock zoom off entirely
+    pub fn set_zoom_limits(&mut self, min: f64, max: f64) {
+        self.min_zoom = min.max(MIN_ZOOM);
+        self.max_zoom = max.max(self.min_zoom);
+        self.target_zoom = self.target_zoom.clamp(self.min_zoom, self.max_zoom);
+    }
+
+    // Zoom at which a source element `element_width` px wide fills
+    // `desired_fraction` of the output, within this rig's zoom limits
+    pub fn zoom_for_onscreen_size(&self, element_width: f64, desired_fraction: f64) -> f64 {
+        onscreen_zoom(element_width, desired_fraction, self.src_width, self.min_zoom, self.max_zoom)
+    }
+
+    // How quickly zoom changes settle: each `tau_seconds` closes ~63% of the
+    // remaining gap, at any frame rate. Pass 0 to jump straight to the target.
+    pub fn set_zoom_speed(&mut self, tau_seconds: f64) {
+        self.zoom_tau = if tau_seconds.is_finite() { tau_seconds.max(0.0) } else { ZOOM_TAU };
+    }
+
+    pub fn get_zoom_speed(&self) -> f64 {
+        self.zoom_tau
+    }
+
+    // The captured surface changed size (window switch, display rotation).
+    // Position, velocity and the tracked target scale with the source so the
+    // framing stays put, then everything is re-clamped to the new bounds.
+    pub fn set_source_size(&mut self, width: f64, height: f64) {
+        if !(width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0) {
+            return;
+        }
+        let sx = width / self.src_width;
+        let sy = height / self.src_height;
+        self.src_width = width;
+        self.src_height = height;
+
+        self.x *= sx;
+        self.y *= sy;
+        self.vx *= sx;
+        self.vy *= sy;
+        self.filtered_target = self.filtered_target.map(|(x, y)| (x * sx, y * sy));
+        self.prev_target = self.prev_target.map(|(x, y)| (x * sx, y * sy));
+        self.aim = self.aim.map(|(x, y)| (x * sx, y * sy));
+        self.lock_x = self.lock_x.map(|x| x * sx);
+        self.lock_y = self.lock_y.map(|y| y * sy);
+        // Tween endpoints and activity history are in old source px
+        self.tween = None;
+        self.fixed = FixedClock::default();
+        self.smoothed = None;
+        if let Some(auto_zoom) = self.auto_zoom.as_mut() {
+            auto_zoom.history.clear();
+        }
+
+        self.zoom_level = self.zoom_level.clamp(self.min_zoom, self.max_zoom);
+        let (min_x, max_x, min_y, max_y) = self.bounds(self.zoom_level);
+        self.x = self.x.clamp(min_x, max_x);
+        self.y = self.y.clamp(min_y, max_y);
+    }
+
+    // Ignore target movements within `radius` source px of the camera, so only
+    // damping acts until the target leaves the zone. 0 disables it.
+    pub fn set_dead_zone(&mut self, radius: f64) {
+        self.dead_zone = radius.max(0.0);
+    }
+
+    // Keep the view `px` source px inside the source edges wherever the zoom
+    // leaves room for it. 0 lets the view touch the edges.
+    pub fn set_bounds_margin(&mut self, px: f64) {
+        self.bounds_margin = if px.is_finite() { px.max(0.0) } else { 0.0 };
+    }
+
+    // Pan on one axis only: a locked axis stays at the source center whatever
+    // the target does, e.g. lock_x for scrolling down a long document
+    pub fn set_axis_lock(&mut self, lock_x: bool, lock_y: bool) {
+        let x = lock_x.then_some(self.src_width / 2.0);
+        let y = lock_y.then_some(self.src_height / 2.0);
+        self.set_axis_lock_at(x, y);
+    }
+
+    // Lock each given axis at that source coordinate (clamped to the bounds
+    // at the current zoom); None unlocks it. Takes effect immediately.
+    pub fn set_axis_lock_at(&mut self, x: Option<f64>, y: Option<f64>) {
+        self.lock_x = x.filter(|x| x.is_finite());
+        self.lock_y = y.filter(|y| y.is_finite());
+        self.apply_axis_lock();
+    }
+
+    // Shape the crop rect to `ratio` (e.g. 16/9) whatever the source's aspect:
+    // a wider view is trimmed at the sides, a narrower one widened into the
+    // surrounding source, and where even the full source width is too narrow
+    // `draw_params` pillarboxes instead of cropping further. 0 turns it off.
+    pub fn set_output_aspect(&mut self, ratio: f64) {
+        self.output_aspect = if ratio.is_finite() && ratio > 0.0 { ratio } else { 0.0 };
+    }
+
+    // Limit pan speed so target teleports don't whip the camera across the frame.
+    // Non-positive values remove the limit.
+    pub fn set_max_speed(&mut self, px_per_sec: f64) {
+        self.max_speed = if px_per_sec.is_finite() && px_per_sec > 0.0 { px_per_sec } else { 0.0 };
+    }
+
+    // Aim at where the target will be `seconds` from now, based on its velocity
+    // across frames. 0 disables prediction.
+    pub fn set_lookahead(&mut self, seconds: f64) {
+        self.lookahead = if seconds.is_finite() { seconds.max(0.0) } else { 0.0 };
+    }
+
+    // Move to (x, y, zoom) over exactly `duration_sec`, ignoring the targets passed
+    // to `update` until the move completes; the spring then takes over from there.
+    // The endpoint is clamped to the zoom limits and bounds.
+    pub fn tween_to(&mut self, x: f64, y: f64, zoom: f64, duration_sec: f64, easing: Easing) {
+        if !self.accepts_finite("tween_to", &[x, y, zoom, duration_sec]) {
+            return;
+        }
+        let zoom = zoom.clamp(self.min_zoom, self.max_zoom);
+        let (min_x, max_x, min_y, max_y) = self.bounds(zoom);
+        self.tween = Some(Tween {
+            from: (self.x, self.y, self.zoom_level),
+            to: (x.clamp(min_x, max_x), y.clamp(min_y, max_y), zoom),
+            duration: duration_sec.max(0.0),
+            elapsed: 0.0,
+            easing,
+        });
+        // The scripted move fully defines the motion; drop any momentum
+        self.vx = 0.0;
+        self.vy = 0.0;
+    }
+
+    pub fn is_tweening(&self) -> bool {
+        self.tween.is_some()
+    }
+
+    // True once the camera is within `pos_epsilon` source px of what it's
+    // following (a target inside the dead zone counts as reached), slower than
+    // `vel_epsilon` px/sec, and done zooming: the view edges are within
+    // `pos_epsilon` of where the target zoom puts them. Never settled mid-tween.
+    pub fn is_settled(&self, pos_epsilon: f64, vel_epsilon: f64) -> bool {
+        if self.tween.is_some() || self.vx.hypot(self.vy) > vel_epsilon {
+            return false;
+        }
+        // Without a target to chase, position is settled wherever it is
+        let distance = match self.aim {
+            Some((aim_x, aim_y)) if self.following => (aim_x - self.x).hypot(aim_y - self.y),
+            _ => 0.0,
+        };
+        if distance > pos_epsilon.max(self.dead_zone) {
+            return false;
+        }
+        let edge_travel = (self.src_width / self.zoom_level - self.src_width / self.target_zoom).abs() / 2.0;
+        edge_travel <= pos_epsilon
+    }
+
+    // Advances the active tween by `dt`, ending it once the duration has elapsed
+    fn advance_tween(&mut self, mut tween: Tween, dt: f64) {
+        tween.elapsed += dt.max(0.0);
+        let t = if tween.duration > 0.0 { (tween.elapsed / tween.duration).min(1.0) } else { 1.0 };
+        let e = tween.easing.apply(t);
+
+        let (fx, fy, fz) = tween.from;
+        let (tx, ty, tz) = tween.to;
+        self.zoom_level = fz + (tz - fz) * e;
+        // Interpolated points can stray outside the bounds of the intermediate zoom
+        let (min_x, max_x, min_y, max_y) = self.bounds(self.zoom_level);
+        let (prev_x, prev_y) = (self.x, self.y);
+        self.x = (fx + (tx - fx) * e).clamp(min_x, max_x);
+        self.y = (fy + (ty - fy) * e).clamp(min_y, max_y);
+        // Keep the velocity meaningful for readers and for the hand-off to the spring
+        if dt > 0.0 {
+            self.vx = (self.x - prev_x) / dt;
+            self.vy = (self.y - prev_y) / dt;
+        }
+
+        if t >= 1.0 {
+            // Hold the final zoom instead of easing back to the old target
+            self.target_zoom = tz;
+            self.tween = None;
+            self.prev_target = None;
+        } else {
+            self.tween = Some(tween);
+        }
+    }
+
+    // Let target activity pick the zoom: movement concentrated within
+    // `detail_spread` source px (RMS, over the last `window_sec`) zooms to
+    // `detail_zoom`, movement spread past `travel_spread` zooms out to 1.0,
+    // blending in between. Overrides `set_target_zoom` until disabled.
+    pub fn enable_auto_zoom(&mut self, window_sec: f64, detail_spread: f64, travel_spread: f64, detail_zoom: f64) {
+        let detail_spread = detail_spread.max(0.0);
+        self.auto_zoom = Some(AutoZoom {
+            window: window_sec.max(0.0),
+            detail_spread,
+            // Keep the blend range non-empty
+            travel_spread: travel_spread.max(detail_spread + 1.0),
+            detail_zoom: detail_zoom.max(1.0),
+            elapsed: 0.0,
+            history: std::collections::VecDeque::new(),
+        });
+    }
+
+    // Back to manual zoom; the current target zoom is kept
+    pub fn disable_auto_zoom(&mut self) {
+        self.auto_zoom = None;
+    }
+
+    // When enabled, zoom changes keep the content under the target fixed on
+    // screen (like a magnifier) rather than zooming about the view center
+    pub fn set_zoom_focus_follow(&mut self, enabled: bool) {
+        self.zoom_focus_follow = enabled;
+    }
+
+    // Hold position and zoom while disabled; residual velocity bleeds off through
+    // damping, and re-enabling resumes the spring from wherever the camera is.
+    pub fn set_following(&mut self, enabled: bool) {
+        if !enabled {
+            // A stale previous target would read as a huge velocity on resume
+            self.prev_target = None;
+        }
+        self.following = enabled;
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.following
+    }
+
+    // Low-pass the incoming target before the spring sees it, removing jitter
+    // above `cutoff_hz` while keeping deliberate motion. Pass 0 to disable.
+    pub fn set_target_lowpass(&mut self, cutoff_hz: f64) {
+        self.target_lowpass_hz = if cutoff_hz.is_finite() && cutoff_hz > 0.0 {
+            cutoff_hz
+        } else {
+            0.0
+        };
+        // Restart the filter from the next raw target
+        self.filtered_target = None;
+    }
+
+    pub fn get_target_lowpass(&self) -> f64 {
+        self.target_lowpass_hz
+    }
+
+    // Range the view center can occupy at `zoom` while staying inside the source
+    fn bounds(&self, zoom: f64) -> (f64, f64, f64, f64) {
+        // Calculate view dimensions at current zoom
+        // When zoomed in, we see less of the source video
+        let view_w = self.src_width / zoom;
+        let view_h = self.src_height / zoom;
+
+        // Calculate safe bounds for camera position
+        // The camera (x, y) represents the center of our view
+        // So min/max are constrained to keep the view within source bounds
+        let min_x = view_w / 2.0;
+        let max_x = self.src_width - view_w / 2.0;
+        let min_y = view_h / 2.0;
+        let max_y = self.src_height - view_h / 2.0;
+
+        // Inset by the margin, but never past the middle of the range: a margin
+        // too large for this zoom collapses the range onto the source center
+        let margin_x = self.bounds_margin.min((max_x - min_x) / 2.0);
+        let margin_y = self.bounds_margin.min((max_y - min_y) / 2.0);
+        (min_x + margin_x, max_x - margin_x, min_y + margin_y, max_y - margin_y)
+    }
+
+    // A NaN or infinite target or dt (say, from a glitched performance.now())
+    // would poison the spring state for good, so that frame is dropped whole.
+    // Warns the first time so the upstream bug can be found.
+    fn accepts_input(&mut self, target_x: f64, target_y: f64, dt: f64) -> bool {
+        self.accepts_finite("update", &[target_x, target_y, dt])
+    }
+
+    // The same guard for any call named `what`: false (after warning once)
+    // if one of `values` is NaN or infinite
+    fn accepts_finite(&mut self, what: &str, values: &[f64]) -> bool {
+        if values.iter().all(|v| v.is_finite()) {
+            return true;
+        }
+        if !self.warned_non_finite {
+            self.warned_non_finite = true;
+            log::warn(&format!("CameraRig: ignoring {} with non-finite input {:?}", what, values));
+        }
+        false
+    }
+
+    // With finite inputs the physics must stay finite
+    fn check_finite(&self) {
+        debug_assert!(
+            [self.x, self.y, self.vx, self.vy, self.zoom_level].iter().all(|v| v.is_finite()),
+            "CameraRig state went non-finite"
+        );
+    }
+
+    fn filter_target(&mut self, target_x: f64, target_y: f64, dt: f64) -> (f64, f64) {
+        if self.target_lowpass_hz <= 0.0 {
+            return (target_x, target_y);
+        }
+
+        let filtered = match self.filtered_target {
+            Some((fx, fy)) => {
+                // Exact discretization of a first-order RC filter, stable for any dt
+                let alpha = 1.0 - (-2.0 * std::f64::consts::PI * self.target_lowpass_hz * dt).exp();
+                (fx + (target_x - fx) * alpha, fy + (target_y - fy) * alpha)
+            }
+            None => (target_x, target_y),
+        };
+        self.filtered_target = Some(filtered);
+        filtered
+    }
+
+    // Leads the target by its frame-to-frame velocity when look-ahead is on
+    fn predict_target(&mut self, target_x: f64, target_y: f64, dt: f64) -> (f64, f64) {
+        let prev = self.prev_target.replace((target_x, target_y));
+        match prev {
+            Some((px, py)) if self.lookahead > 0.0 && dt > 0.0 => {
+                let vx = (target_x - px) / dt;
+                let vy = (target_y - py) / dt;
+                (target_x + vx * self.lookahead, target_y + vy * self.lookahead)
+            }
+            _ => (target_x, target_y),
+        }
+    }
+
+    // One explicit Euler step of the follow spring
+    fn integrate(&mut self, target_x: f64, target_y: f64, dt: f64) {
+        // Apply physics to x,y using clamped target
+        let mut dist_x = target_x - self.x;
+        let mut dist_y = target_y - self.y;
+
+        // Inside the dead zone the target counts as reached
+        if dist_x.hypot(dist_y) <= self.dead_zone {
+            dist_x = 0.0;
+            dist_y = 0.0;
+        }
+        
+        let force_x = self.stiffness_x * dist_x;
+        let force_y = self.stiffness_y * dist_y;
+
+        let accel_x = (force_x - self.damping_x * self.vx) / self.mass;
+        let accel_y = (force_y - self.damping_y * self.vy) / self.mass;
+
+        self.vx += accel_x * dt;
+        self.vy += accel_y * dt;
+
+        // Cap speed before integrating position so one huge dt can't overshoot
+        let speed = self.vx.hypot(self.vy);
+        if self.max_speed > 0.0 && speed > self.max_speed {
+            let scale = self.max_speed / speed;
+            self.vx *= scale;
+            self.vy *= scale;
+        }
+
+        self.x += self.vx * dt;
+        self.y += self.vy * dt;
+    }
+
+    pub fn update(&mut self, target_x: f64, target_y: f64, dt: f64) {
+        if !self.accepts_input(target_x, target_y, dt) {
+            return;
+        }
+        self.updated = true;
+        self.step(target_x, target_y, dt);
+        self.check_finite();
+        self.fixed = FixedClock::default();
+        self.smooth_output(dt);
+        let (x, y, zoom) = self.pose();
+        self.path.record(dt, x, y, zoom);
+    }
+
+    // Like `update`, but the physics always advances in FIXED_STEP increments
+    // however long `real_dt` is, so the motion is identical at any frame rate
+    // (and reproducible in tests). Leftover time is carried to the next call,
+    // and the getters show a blend between the last two steps meanwhile.
+    pub fn update_fixed(&mut self, target_x: f64, target_y: f64, real_dt: f64) {
+        if !self.accepts_input(target_x, target_y, real_dt) {
+            return;
+        }
+        self.updated = true;
+        let max_dt = MAX_PHYSICS_STEP * MAX_PHYSICS_SUBSTEPS as f64;
+        let mut clock = self.fixed;
+        clock.accumulator += real_dt.clamp(0.0, max_dt);
+        while clock.accumulator >= FIXED_STEP {
+            clock.prev = Some((self.x, self.y, self.zoom_level));
+            self.step(target_x, target_y, FIXED_STEP);
+            clock.accumulator -= FIXED_STEP;
+        }
+        self.check_finite();
+
+        let current = (self.x, self.y, self.zoom_level);
+        let (px, py, pzoom) = clock.prev.unwrap_or(current);
+        let alpha = clock.accumulator / FIXED_STEP;
+        clock.pose = Some((
+            px + (current.0 - px) * alpha,
+            py + (current.1 - py) * alpha,
+            pzoom + (current.2 - pzoom) * alpha,
+        ));
+        self.fixed = clock;
+        self.smooth_output(real_dt);
+        let (x, y, zoom) = self.pose();
+        self.path.record(real_dt, x, y, zoom);
+    }
+
+    // Exponential smoothing of the shown position on top of the physics, for
+    // tripod-steady output when the target wobbles at the dead-zone edge.
+    // `factor` is the time constant in seconds: higher lags more. 0 turns it off.
+    pub fn set_smoothing(&mut self, factor: f64) {
+        self.smoothing = if factor.is_finite() { factor.max(0.0) } else { 0.0 };
+        if self.smoothing == 0.0 {
+            self.smoothed = None;
+        }
+    }
+
+    // Moves the smoothed position towards the unsmoothed one; the physics
+    // itself never sees the smoothing
+    fn smooth_output(&mut self, dt: f64) {
+        if self.smoothing <= 0.0 {
+            return;
+        }
+        let (x, y, zoom) = self.fixed.pose.unwrap_or((self.x, self.y, self.zoom_level));
+        let (sx, sy) = self.smoothed.unwrap_or((x, y));
+        let alpha = 1.0 - (-dt.max(0.0) / self.smoothing).exp();
+        // The lagging position must still respect the bounds at the current zoom
+        let (min_x, max_x, min_y, max_y) = self.bounds(zoom);
+        self.smoothed = Some((
+            (sx + (x - sx) * alpha).clamp(min_x, max_x),
+            (sy + (y - sy) * alpha).clamp(min_y, max_y),
+        ));
+    }
+
+    // Begin logging `{ t, x, y, zoom }` after every `update`, discarding any
+    // previous recording. Past `max_samples` the log is decimated 2:1.
+    pub fn start_path_recording(&mut self, max_samples: Option<u32>) {
+        self.path = PathRecorder {
+            recording: true,
+            max_samples: max_samples.map_or(DEFAULT_PATH_MAX_SAMPLES, |max| max.max(2) as usize),
+            ..PathRecorder::default()
+        };
+    }
+
+    // Stops appending; the recorded samples stay available to export
+    pub fn stop_path_recording(&mut self) {
+        self.path.recording = false;
+    }
+
+    // Same samples as CSV with a `t,x,y,zoom` header row
+    pub fn export_path_csv(&self) -> String {
+        let mut csv = String::from("t,x,y,zoom\n");
+        for sample in &self.path.samples {
+            csv.push_str(&format!("{},{},{},{}\n", sample.t, sample.x, sample.y, sample.zoom));
+        }
+        csv
+    }
+
+    fn step(&mut self, target_x: f64, target_y: f64, dt: f64) {
+        self.advance(target_x, target_y, dt);
+        self.apply_axis_lock();
+    }
+
+    // Pins the locked axes, whatever moved them
+    fn apply_axis_lock(&mut self) {
+        if self.lock_x.is_none() && self.lock_y.is_none() {
+            return;
+        }
+        let (min_x, max_x, min_y, max_y) = self.bounds(self.zoom_level);
+        if let Some(x) = self.lock_x {
+            self.x = x.clamp(min_x, max_x);
+            self.vx = 0.0;
+        }
+        if let Some(y) = self.lock_y {
+            self.y = y.clamp(min_y, max_y);
+            self.vy = 0.0;
+        }
+    }
+
+    // One step of whichever motion is active: tween, idle drift or the spring
+    fn advance(&mut self, target_x: f64, target_y: f64, dt: f64) {
+        if let Some(tween) = self.tween {
+            self.advance_tween(tween, dt);
+            return;
+        }
+
+        if !self.following {
+            // Exact decay of the damping term alone, so momentum is gone (not
+            // frozen) by the time following resumes
+            self.vx *= (-self.damping_x / self.mass * dt.max(0.0)).exp();
+            self.vy *= (-self.damping_y / self.mass * dt.max(0.0)).exp();
+            return;
+        }
+
+        // Remove high-frequency tracker jitter before anything else
+        let (target_x, target_y) = self.filter_target(target_x, target_y, dt);
+        if let Some(auto_zoom) = self.auto_zoom.as_mut() {
+            let zoom = auto_zoom.observe(target_x, target_y, dt);
+            self.target_zoom = zoom.clamp(self.min_zoom, self.max_zoom);
+        }
+        let (target_x, target_y) = self.predict_target(target_x, target_y, dt);
+
+        // Smooth zoom first to know our constraints
+        let prev_zoom = self.zoom_level;
+        // Exact exponential approach, so a long frame lands where several
+        // short ones would instead of overshooting
+        let zoom_diff = self.target_zoom - self.zoom_level;
+        let alpha = if self.zoom_tau > 0.0 { 1.0 - (-dt.max(0.0) / self.zoom_tau).exp() } else { 1.0 };
+        self.zoom_level += zoom_diff * alpha;
+        
+        // Clamp zoom to safe range
+        self.zoom_level = self.zoom_level.clamp(self.min_zoom, self.max_zoom);
+
+        let (min_x, max_x, min_y, max_y) = self.bounds(self.zoom_level);
+
+        // Magnifier-style zoom: keep the target at the same spot on screen by
+        // scaling its offset from the view center with the zoom change
+        if self.zoom_focus_follow && self.zoom_level != prev_zoom {
+            let scale = prev_zoom / self.zoom_level;
+            self.x = (target_x - (target_x - self.x) * scale).clamp(min_x, max_x);
+            self.y = (target_y - (target_y - self.y) * scale).clamp(min_y, max_y);
+        }
+
+        // Clamp target to valid bounds before applying physics (a locked axis
+        // aims at its lock position, so it feels no pull)
+        let clamped_target_x = self.lock_x.unwrap_or(target_x).clamp(min_x, max_x);
+        let clamped_target_y = self.lock_y.unwrap_or(target_y).clamp(min_y, max_y);
+        self.aim = Some((clamped_target_x, clamped_target_y));
+
+        // Explicit Euler goes unstable on long frames (e.g. after a stall),
+        // so integrate in fixed-size sub-steps
+        let physics_dt = dt.min(MAX_PHYSICS_STEP * MAX_PHYSICS_SUBSTEPS as f64);
+        let steps = (physics_dt / MAX_PHYSICS_STEP).ceil().max(1.0);
+        let step_dt = physics_dt / steps;
+        for _ in 0..steps as u32 {
+            self.integrate(clamped_target_x, clamped_target_y, step_dt);
+        }
+
+        // Final clamp to ensure we never exceed bounds (safety net)
+        if self.x < min_x { 
+            self.x = min_x; 
+            self.vx = 0.0; 
+        }
+        if self.x > max_x { 
+            self.x = max_x; 
+            self.vx = 0.0; 
+        }
+        if self.y < min_y { 
+            self.y = min_y; 
+            self.vy = 0.0; 
+        }
+        if self.y > max_y { 
+            self.y = max_y; 
+            self.vy = 0.0; 
+        }
+    }
+
+    // Follow the center of `rect` (source px, top-left + size) and zoom so the rect
+    // plus `padding` px on every side fits in the view
+    pub fn frame_rect(&mut self, rect: Rect, padding: f64, dt: f64) {
+        let padded_w = rect.width + 2.0 * padding.max(0.0);
+        let padded_h = rect.height + 2.0 * padding.max(0.0);
+        self.set_target_zoom(self.fit_zoom(padded_w, padded_h));
+        self.update(rect.x + rect.width / 2.0, rect.y + rect.height / 2.0, dt);
+    }
+
+    // Zoom at which a `width` x `height` area fills the view
+    fn fit_zoom(&self, width: f64, height: f64) -> f64 {
+        let zoom = (self.src_width / width).min(self.src_height / height);
+        // An area bigger than the source (or a degenerate one) just shows everything
+        if zoom.is_finite() && zoom >= 1.0 { zoom } else { 1.0 }
+    }
+
+    // With a padding, `update_multi` also zooms so that every high-weight
+    // point (at least half the top weight) stays `padding` px inside the view.
+    // Pass nothing to leave the zoom to its other controls.
+    pub fn set_multi_target_zoom(&mut self, padding: Option<f64>) {
+        self.multi_zoom_padding = padding.filter(|p| p.is_finite()).map(|p| p.max(0.0));
+    }
+}
+
+// Slices of structs can't cross into JS; `update_multi` takes them as a JsValue
+impl CameraRig {
+    // Follow the weighted centroid of several points of interest instead of
+    // jumping between them. Points with a non-positive or non-finite weight
+    // are ignored; a single point behaves exactly like `update`, and none
+    // holds the camera where it is.
+    pub fn update_points(&mut self, targets: &[TargetPoint], dt: f64) {
+        let points: Vec<TargetPoint> = targets.iter().copied()
+            .filter(|p| p.x.is_finite() && p.y.is_finite() && p.weight.is_finite() && p.weight > 0.0)
+            .collect();
+        if let [point] = points.as_slice() {
+            self.update(point.x, point.y, dt);
+            return;
+        }
+        if points.is_empty() {
+            let (x, y, _) = self.pose();
+            self.update(x, y, dt);
+            return;
+        }
+
+        let total: f64 = points.iter().map(|p| p.weight).sum();
+        let cx = points.iter().map(|p| p.x * p.weight).sum::<f64>() / total;
+        let cy = points.iter().map(|p| p.y * p.weight).sum::<f64>() / total;
+        if let Some(padding) = self.multi_zoom_padding {
+            // The view is centered on the centroid, so it must reach the
+            // farthest important point on each side of it
+            let top = points.iter().map(|p| p.weight).fold(0.0, f64::max);
+            let (half_w, half_h) = points.iter()
+                .filter(|p| p.weight >= top / 2.0)
+                .fold((0.0f64, 0.0f64), |(w, h), p| (w.max((p.x - cx).abs()), h.max((p.y - cy).abs())));
+            let zoom = self.fit_zoom(2.0 * (half_w + padding), 2.0 * (half_h + padding));
+            self.set_target_zoom(zoom);
+        }
+        self.update(cx, cy, dt);
+    }
+}
+
+// Plain-Rust accessors behind the JS getters
+impl CameraRig {
+    // Centered position and scale for JS to apply as a transform
+    pub fn view(&self) -> ViewState {
+        let (x, y, zoom) = self.pose();
+        ViewState { x, y, zoom }
+    }
+
+    // The displayed (x, y, zoom), which lags the physics under `update_fixed`
+    // and output smoothing
+    fn pose(&self) -> (f64, f64, f64) {
+        let (x, y, zoom) = self.fixed.pose.unwrap_or((self.x, self.y, self.zoom_level));
+        match self.smoothed {
+            Some((sx, sy)) => (sx, sy, zoom),
+            None => (x, y, zoom),
+        }
+    }
+
+    // What `update` clamps the center to at the current `zoom_level`
+    pub fn view_bounds(&self) -> ViewBounds {
+        let (min_x, max_x, min_y, max_y) = self.bounds(self.zoom_level);
+        ViewBounds {
+            min_x,
+            max_x,
+            min_y,
+            max_y,
+            view_width: self.src_width / self.zoom_level,
+            view_height: self.src_height / self.zoom_level,
+        }
+    }
+
+    // Camera velocity after the latest `update`, in source px/sec
+    pub fn velocity(&self) -> Velocity {
+        Velocity {
+            vx: self.vx,
+            vy: self.vy,
+            speed: self.vx.hypot(self.vy),
+        }
+    }
+
+    // The exact source region to crop, in source pixels (top-left + size)
+    pub fn crop_rect(&self) -> Rect {
+        let (x, y, zoom) = self.pose();
+        let mut width = (self.src_width / zoom).min(self.src_width);
+        let height = (self.src_height / zoom).min(self.src_height);
+        if self.output_aspect > 0.0 {
+            if width / height > self.output_aspect {
+                width = height * self.output_aspect;
+            } else {
+                // Never narrower than the ratio needs, but never past the source
+                // either; what's still missing becomes bars in `draw_params`
+                width = (height * self.output_aspect).min(self.src_width);
+            }
+        }
+        Rect {
+            x: (x - width / 2.0).clamp(0.0, self.src_width - width),
+            y: (y - height / 2.0).clamp(0.0, self.src_height - height),
+            width,
+            height,
+        }
+    }
+
+    // How to draw the current view onto an `out_width` x `out_height` canvas.
+    // Normally the view fills the whole canvas; when the aspect ratios differ the
+    // crop rect is trimmed around its center rather than stretched or letterboxed.
+    // With an output aspect set the crop is drawn whole and centered instead,
+    // with bars wherever its shape differs from the canvas.
+    pub fn draw_params(&self, out_width: f64, out_height: f64) -> DrawParams {
+        let crop = self.crop_rect();
+        if self.output_aspect > 0.0 {
+            let scale = (out_width / crop.width).min(out_height / crop.height);
+            let (dw, dh) = (crop.width * scale, crop.height * scale);
+            return DrawParams {
+                sx: crop.x,
+                sy: crop.y,
+                sw: crop.width,
+                sh: crop.height,
+                dx: (out_width - dw) / 2.0,
+                dy: (out_height - dh) / 2.0,
+                dw,
+                dh,
+            };
+        }
+        let (mut sw, mut sh) = (crop.width, crop.height);
+        let out_aspect = out_width / out_height;
+        if out_aspect.is_finite() && out_aspect > 0.0 {
+            if sw / sh > out_aspect {
+                sw = sh * out_aspect;
+            } else {
+                sh = sw / out_aspect;
+            }
+        }
+        DrawParams {
+            sx: crop.x + (crop.width - sw) / 2.0,
+            sy: crop.y + (crop.height - sh) / 2.0,
+            sw,
+            sh,
+            dx: 0.0,
+            dy: 0.0,
+            dw: out_width,
+            dh: out_height,
+        }
+    }
+
+    pub fn path(&self) -> &[PathSample] {
+        &self.path.samples
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+impl CameraRig {
+    // Rebuild a rig from `get_state`; it continues exactly where the original left off
+    pub fn from_state(state: JsValue) -> Result<CameraRig, JsValue> {
+        serde_wasm_bindgen::from_value(state)
+            .map_err(|err| js_sys::Error::new(&format!("Invalid CameraRig state: {}", err)).into())
+    }
+
+    // Every internal field, including velocity and filter history, as a plain object
+    pub fn get_state(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self).unwrap()
+    }
+
+    // Recorded samples as an array of `{ t, x, y, zoom }`
+    pub fn export_path(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.path()).unwrap()
+    }
+
+    pub fn get_velocity(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.velocity()).unwrap()
+    }
+
+    pub fn get_view_rect(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.view()).unwrap()
+    }
+
+    // `{ min_x, max_x, min_y, max_y, view_width, view_height }` for guide overlays
+    pub fn get_bounds(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.view_bounds()).unwrap()
+    }
+
+    pub fn get_crop_rect(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.crop_rect()).unwrap()
+    }
+
+    // `update_points` for an array of `{ x, y, weight }`
+    pub fn update_multi(&mut self, targets: JsValue, dt: f64) -> Result<(), JsValue> {
+        let targets: Vec<TargetPoint> = serde_wasm_bindgen::from_value(targets)
+            .map_err(|err| js_sys::Error::new(&format!("Invalid targets: {}", err)))?;
+        self.update_points(&targets, dt);
+        Ok(())
+    }
+
+    // `{ sx, sy, sw, sh, dx, dy, dw, dh }` for `drawImage`
+    #[wasm_bindgen(js_name = draw_params)]
+    pub fn get_draw_params(&self, out_width: f64, out_height: f64) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.draw_params(out_width, out_height)).unwrap()
+    }
+}
+
+// All of a rig's settings up front, checked together once in `build`:
+// new CameraRigBuilder(w, h).spring(200, 28, 1).zoom_limits(1, 3).build()
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug)]
+pub struct CameraRigBuilder {
+    src_width: f64,
+    src_height: f64,
+    // (stiffness, damping, mass)
+    spring: Option<(f64, f64, f64)>,
+    damping_ratio: Option<f64>,
+    zoom_limits: Option<(f64, f64)>,
+    dead_zone: f64,
+    bounds_margin: f64,
+    max_speed: f64,
+    lookahead: f64,
+    target_lowpass_hz: f64,
+    smoothing: f64,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl CameraRigBuilder {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(src_width: f64, src_height: f64) -> CameraRigBuilder {
+        CameraRigBuilder {
+            src_width,
+            src_height,
+            spring: None,
+            damping_ratio: None,
+            zoom_limits: None,
+            dead_zone: 0.0,
+            bounds_margin: 0.0,
+            max_speed: 0.0,
+            lookahead: 0.0,
+            target_lowpass_hz: 0.0,
+            smoothing: 0.0,
+        }
+    }
+
+    pub fn spring(mut self, stiffness: f64, damping: f64, mass: f64) -> CameraRigBuilder {
+        self.spring = Some((stiffness, damping, mass));
+        self
+    }
+
+    // Overrides the spring's damping with this multiple of critical damping
+    pub fn damping_ratio(mut self, ratio: f64) -> CameraRigBuilder {
+        self.damping_ratio = Some(ratio);
+        self
+    }
+
+    pub fn spring_preset(self, preset: SpringPreset) -> CameraRigBuilder {
+        let (stiffness, ratio) = preset.params();
+        self.spring(stiffness, 0.0, CAMERA_MASS).damping_ratio(ratio)
+    }
+
+    pub fn zoom_limits(mut self, min: f64, max: f64) -> CameraRigBuilder {
+        self.zoom_limits = Some((min, max));
+        self
+    }
+
+    pub fn dead_zone(mut self, radius: f64) -> CameraRigBuilder {
+        self.dead_zone = radius;
+        self
+    }
+
+    pub fn bounds_margin(mut self, px: f64) -> CameraRigBuilder {
+        self.bounds_margin = px;
+        self
+    }
+
+    // 0 leaves the speed unlimited
+    pub fn max_speed(mut self, px_per_sec: f64) -> CameraRigBuilder {
+        self.max_speed = px_per_sec;
+        self
+    }
+
+    pub fn lookahead(mut self, seconds: f64) -> CameraRigBuilder {
+        self.lookahead = seconds;
+        self
+    }
+
+    pub fn target_lowpass(mut self, cutoff_hz: f64) -> CameraRigBuilder {
+        self.target_lowpass_hz = cutoff_hz;
+        self
+    }
+
+    pub fn smoothing(mut self, factor: f64) -> CameraRigBuilder {
+        self.smoothing = factor;
+        self
+    }
+
+    // Unlike the setters, which clamp bad values quietly, this rejects them
+    pub fn build(self) -> Result<CameraRig, CameraError> {
+        let invalid = |msg: String| Err(CameraError::InvalidConfig(msg));
+        if !(self.src_width.is_finite() && self.src_height.is_finite())
+            || self.src_width <= 0.0
+            || self.src_height <= 0.0
+        {
+            return invalid(format!("source size must be positive, got {}x{}", self.src_width, self.src_height));
+        }
+        if let Some((stiffness, damping, mass)) = self.spring {
+            if !stiffness.is_finite() || stiffness < MIN_STIFFNESS {
+                return invalid(format!("stiffness must be positive, got {}", stiffness));
+            }
+            if !damping.is_finite() || damping < 0.0 {
+                return invalid(format!("damping must be non-negative, got {}", damping));
+            }
+            if !mass.is_finite() || mass < MIN_MASS {
+                return invalid(format!("mass must be positive, got {}", mass));
+            }
+        }
+        if let Some(ratio) = self.damping_ratio {
+            if !ratio.is_finite() || ratio < 0.0 {
+                return invalid(format!("damping ratio must be non-negative, got {}", ratio));
+            }
+        }
+        if let Some((min, max)) = self.zoom_limits {
+            if !(min.is_finite() && max.is_finite()) || min < MIN_ZOOM {
+                return invalid(format!("min zoom must be at least {}, got {}", MIN_ZOOM, min));
+            }
+            if min > max {
+                return invalid(format!("min zoom {} exceeds max zoom {}", min, max));
+            }
+        }
+        for (name, value) in [
+            ("dead zone", self.dead_zone),
+            ("bounds margin", self.bounds_margin),
+            ("max speed", self.max_speed),
+            ("lookahead", self.lookahead),
+            ("target lowpass", self.target_lowpass_hz),
+            ("smoothing", self.smoothing),
+        ] {
+            if !value.is_finite() || value < 0.0 {
+                return invalid(format!("{} must be non-negative, got {}", name, value));
+            }
+        }
+
+        let mut rig = CameraRig::new(self.src_width, self.src_height);
+        if let Some((stiffness, damping, mass)) = self.spring {
+            rig.set_spring(stiffness, damping, mass);
+        }
+        if let Some(ratio) = self.damping_ratio {
+            rig.set_damping_ratio(ratio);
+        }
+        if let Some((min, max)) = self.zoom_limits {
+            rig.set_zoom_limits(min, max);
+            // Start fully zoomed out within the new limits
+            rig.reset();
+        }
+        rig.set_dead_zone(self.dead_zone);
+        rig.set_bounds_margin(self.bounds_margin);
+        rig.set_max_speed(self.max_speed);
+        rig.set_lookahead(self.lookahead);
+        rig.set_target_lowpass(self.target_lowpass_hz);
+        rig.set_smoothing(self.smoothing);
+        Ok(rig)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converges_on_a_still_target() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.set_target_zoom(2.0);
+        for _ in 0..300 {
+            rig.update(600.0, 400.0, 1.0 / 60.0);
+        }
+        let view = rig.view();
+        assert!((view.x - 600.0).abs() < 0.5 && (view.y - 400.0).abs() < 0.5, "{:?}", view);
+        assert!((view.zoom - 2.0).abs() < 1e-3);
+        assert!(rig.is_settled(0.5, 0.5));
+    }
+
+    #[test]
+    fn targets_are_clamped_to_the_source() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.snap_zoom(2.0);
+        for _ in 0..300 {
+            rig.update(0.0, 5000.0, 1.0 / 60.0);
+        }
+        let bounds = rig.view_bounds();
+        assert_eq!((bounds.min_x, bounds.max_y), (480.0, 810.0));
+        let view = rig.view();
+        assert!((view.x - bounds.min_x).abs() < 0.5 && (view.y - bounds.max_y).abs() < 0.5, "{:?}", view);
+    }
+
+    #[test]
+    fn onscreen_zoom_respects_the_rig_limits() {
+        // A 480 px element filling the 1920 px frame needs 4x
+        assert_eq!(zoom_for_onscreen_size(480.0, 1.0, 1920.0), MAX_ZOOM);
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.set_zoom_limits(1.0, 5.0);
+        assert_eq!(rig.zoom_for_onscreen_size(480.0, 1.0), 4.0);
+        rig.set_zoom_limits(1.5, 1.8);
+        assert_eq!(rig.zoom_for_onscreen_size(480.0, 1.0), 1.8);
+        assert_eq!(rig.zoom_for_onscreen_size(1920.0, 0.5), 1.5);
+        assert_eq!(rig.zoom_for_onscreen_size(0.0, 0.5), 1.5);
+    }
+
+    #[test]
+    fn infinite_settings_keep_the_camera_finite() {
+        let setters: [fn(&mut CameraRig); 6] = [
+            |rig| rig.set_lookahead(f64::INFINITY),
+            |rig| rig.set_spring(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            |rig| rig.set_axis_spring(f64::INFINITY, 1.0, 1.0, f64::INFINITY),
+            |rig| rig.set_critically_damped(f64::INFINITY),
+            |rig| rig.set_damping_ratio(f64::INFINITY),
+            |rig| rig.set_max_speed(f64::INFINITY),
+        ];
+        for (i, set) in setters.iter().enumerate() {
+            let mut rig = CameraRig::new(1920.0, 1080.0);
+            rig.set_target_zoom(2.0);
+            set(&mut rig);
+            for frame in 0..120 {
+                rig.update(400.0 + frame as f64 * 10.0, 300.0, 1.0 / 60.0);
+            }
+            let params = rig.draw_params(1920.0, 1080.0);
+            let values = [params.sx, params.sy, params.sw, params.sh, params.dx, params.dy, params.dw, params.dh];
+            assert!(values.iter().all(|v| v.is_finite()), "setter {}: {:?}", i, params);
+        }
+    }
+
+    #[test]
+    fn non_finite_target_zoom_is_ignored() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.set_target_zoom(2.0);
+        rig.set_target_zoom(f64::NAN);
+        rig.set_target_zoom(f64::INFINITY);
+        for _ in 0..240 {
+            rig.update(960.0, 540.0, 1.0 / 60.0);
+        }
+        let view = rig.view();
+        assert!(view.x.is_finite() && view.y.is_finite());
+        assert!((view.zoom - 2.0).abs() < 1e-3, "zoom {}", view.zoom);
+    }
+
+    #[test]
+    fn restored_state_continues_identically() {
+        for fixed in [false, true] {
+            let mut rig = CameraRig::new(1920.0, 1080.0);
+            rig.set_smoothing(0.2);
+            rig.set_target_zoom(2.0);
+            let step = |rig: &mut CameraRig, i: u32| {
+                let target = (300.0 + i as f64 * 20.0, 700.0 - i as f64 * 5.0);
+                if fixed {
+                    rig.update_fixed(target.0, target.1, 1.0 / 45.0);
+                } else {
+                    rig.update(target.0, target.1, 1.0 / 45.0);
+                }
+            };
+            for i in 0..20 {
+                step(&mut rig, i);
+            }
+            let json = serde_json::to_string(&rig).unwrap();
+            let mut restored: CameraRig = serde_json::from_str(&json).unwrap();
+            step(&mut rig, 20);
+            step(&mut restored, 20);
+            // serde_json's float parsing can be an ulp off; JS objects are exact
+            let (a, b) = (rig.view(), restored.view());
+            for (a, b) in [(a.x, b.x), (a.y, b.y), (a.zoom, b.zoom)] {
+                assert!((a - b).abs() < 1e-9, "fixed {}: {} vs {}", fixed, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_positions_are_ignored() {
+        let mut rig = CameraRig::new_at(1920.0, 1080.0, f64::NAN, 540.0, 2.0);
+        assert_eq!((rig.view().x, rig.view().y), (960.0, 540.0));
+        rig.snap_to(f64::NAN, 300.0);
+        rig.snap_zoom(f64::INFINITY);
+        rig.reset_to(500.0, f64::NAN, 2.0);
+        rig.prime(f64::NAN, f64::NAN);
+        rig.tween_to(700.0, 400.0, f64::NAN, 1.0, Easing::Linear);
+        assert!(!rig.is_tweening());
+        for _ in 0..60 {
+            rig.update(700.0, 400.0, 1.0 / 60.0);
+        }
+        let view = rig.view();
+        assert!(view.x.is_finite() && view.y.is_finite() && view.zoom.is_finite());
+    }
+
+    const DT: f64 = 1.0 / 60.0;
+
+    // A rig zoomed to 2x, so the center can move within [480, 1440] x [270, 810]
+    fn zoomed_rig() -> CameraRig {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.snap_zoom(2.0);
+        rig
+    }
+
+    #[test]
+    fn targets_inside_the_dead_zone_do_not_move_the_camera() {
+        let mut rig = zoomed_rig();
+        rig.set_dead_zone(50.0);
+        for _ in 0..120 {
+            rig.update(1000.0, 560.0, DT);
+        }
+        assert_eq!((rig.view().x, rig.view().y), (960.0, 540.0));
+        assert!(rig.is_settled(0.5, 0.5));
+
+        for _ in 0..120 {
+            rig.update(1200.0, 540.0, DT);
+        }
+        // Pulled until the target is back inside the zone
+        let x = rig.view().x;
+        assert!(x > 1100.0 && x < 1200.0, "x {}", x);
+    }
+
+    #[test]
+    fn pan_speed_never_exceeds_max_speed() {
+        let mut rig = zoomed_rig();
+        rig.set_max_speed(300.0);
+        let mut prev = rig.view();
+        for _ in 0..240 {
+            rig.update(1440.0, 810.0, DT);
+            let view = rig.view();
+            assert!(rig.velocity().speed <= 300.0 + 1e-9, "{:?}", rig.velocity());
+            assert!((view.x - prev.x).hypot(view.y - prev.y) <= 300.0 * DT + 1e-9);
+            prev = view;
+        }
+        // The cap slows the pan down; it doesn't stop it arriving
+        assert!((prev.x - 1440.0).abs() < 1.0 && (prev.y - 810.0).abs() < 1.0, "{:?}", prev);
+    }
+
+    #[test]
+    fn crop_rect_is_the_view_in_source_pixels() {
+        let mut rig = zoomed_rig();
+        rig.snap_to(600.0, 400.0);
+        let crop = rig.crop_rect();
+        assert_eq!((crop.x, crop.y, crop.width, crop.height), (120.0, 130.0, 960.0, 540.0));
+    }
+
+    #[test]
+    fn long_frames_match_many_short_ones() {
+        let mut once = zoomed_rig();
+        let mut often = zoomed_rig();
+        once.update(1300.0, 700.0, 0.5);
+        for _ in 0..30 {
+            often.update(1300.0, 700.0, DT);
+        }
+        let (a, b) = (once.view(), often.view());
+        assert!((a.x - b.x).abs() < 1e-6 && (a.y - b.y).abs() < 1e-6, "{:?} vs {:?}", a, b);
+
+        // A multi-second stall stays stable and in bounds
+        once.update(480.0, 270.0, 10.0);
+        let view = once.view();
+        assert!((view.x - 480.0).abs() < 1.0 && (view.y - 270.0).abs() < 1.0, "{:?}", view);
+    }
+
+    #[test]
+    fn lookahead_leads_a_moving_target() {
+        let mut plain = zoomed_rig();
+        let mut leading = zoomed_rig();
+        leading.set_lookahead(0.5);
+        let mut target = 500.0;
+        for _ in 0..60 {
+            target += 600.0 * DT;
+            plain.update(target, 540.0, DT);
+            leading.update(target, 540.0, DT);
+        }
+        // The spring alone trails the target; half a second of lead overtakes it
+        assert!(plain.view().x < target, "{} vs {}", plain.view().x, target);
+        assert!(leading.view().x > target, "{} vs {}", leading.view().x, target);
+    }
+
+    #[test]
+    fn frame_rect_fits_the_padded_rect() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.set_zoom_limits(1.0, 5.0);
+        for _ in 0..300 {
+            rig.frame_rect(Rect::new(400.0, 300.0, 400.0, 190.0), 40.0, DT);
+        }
+        let view = rig.view();
+        assert!((view.zoom - 4.0).abs() < 1e-3, "zoom {}", view.zoom);
+        assert!((view.x - 600.0).abs() < 0.5 && (view.y - 395.0).abs() < 0.5, "{:?}", view);
+    }
+
+    #[test]
+    fn a_stiffer_axis_responds_faster() {
+        let mut rig = zoomed_rig();
+        rig.set_axis_spring(300.0, 2.0 * 300f64.sqrt(), 30.0, 2.0 * 30f64.sqrt());
+        for _ in 0..20 {
+            rig.update(1160.0, 740.0, DT);
+        }
+        // Same 200 px to cover on each axis
+        let view = rig.view();
+        assert!(view.x - 960.0 > 1.5 * (view.y - 540.0), "{:?}", view);
+    }
+
+    #[test]
+    fn a_rig_that_is_not_following_holds_still() {
+        let mut rig = zoomed_rig();
+        rig.set_following(false);
+        for _ in 0..60 {
+            rig.update(1400.0, 800.0, DT);
+        }
+        assert_eq!((rig.view().x, rig.view().y), (960.0, 540.0));
+
+        rig.set_following(true);
+        for _ in 0..300 {
+            rig.update(1400.0, 800.0, DT);
+        }
+        let view = rig.view();
+        assert!((view.x - 1400.0).abs() < 0.5 && (view.y - 800.0).abs() < 0.5, "{:?}", view);
+    }
+
+    #[test]
+    fn tweens_follow_their_easing_and_end_on_time() {
+        // Progress a quarter of the way through a move of 240 px
+        for (easing, quarter) in [(Easing::Linear, 0.25), (Easing::EaseInOut, 0.15625), (Easing::EaseOut, 0.4375)] {
+            let mut rig = zoomed_rig();
+            rig.tween_to(1200.0, 700.0, 2.0, 1.0, easing);
+            // The targets passed meanwhile are ignored
+            rig.update(0.0, 0.0, 0.25);
+            assert!((rig.view().x - (960.0 + 240.0 * quarter)).abs() < 1e-9, "{:?}: {:?}", easing, rig.view());
+            assert!(rig.is_tweening() && !rig.is_settled(0.5, 0.5));
+            for _ in 0..3 {
+                rig.update(0.0, 0.0, 0.25);
+            }
+            assert!(!rig.is_tweening());
+            assert_eq!((rig.view().x, rig.view().y, rig.view().zoom), (1200.0, 700.0, 2.0));
+        }
+    }
+
+    #[test]
+    fn velocity_matches_the_motion() {
+        let mut rig = zoomed_rig();
+        rig.tween_to(1200.0, 540.0, 2.0, 1.0, Easing::Linear);
+        rig.update(0.0, 0.0, 0.25);
+        let velocity = rig.velocity();
+        assert!((velocity.vx - 240.0).abs() < 1e-9 && velocity.vy == 0.0, "{:?}", velocity);
+        assert!((velocity.speed - 240.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn path_recording_stays_under_its_cap() {
+        let mut rig = zoomed_rig();
+        rig.update(1000.0, 600.0, DT);
+        rig.start_path_recording(Some(4));
+        for _ in 0..10 {
+            rig.update(1000.0, 600.0, DT);
+        }
+        rig.stop_path_recording();
+        rig.update(1000.0, 600.0, DT);
+
+        let path = rig.path();
+        assert!(path.len() >= 2 && path.len() <= 4, "{} samples", path.len());
+        assert!(path.windows(2).all(|pair| pair[0].t < pair[1].t));
+        assert!(path.last().unwrap().t <= 10.0 * DT + 1e-9);
+        assert_eq!(rig.export_path_csv().lines().count(), path.len() + 1);
+    }
+
+    #[test]
+    fn focus_follow_keeps_the_target_still_on_screen() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.snap_zoom(1.5);
+        // So only the zoom moves the camera
+        rig.set_dead_zone(10_000.0);
+        rig.set_zoom_focus_follow(true);
+        rig.set_target_zoom(2.5);
+        let on_screen = |rig: &CameraRig| {
+            let view = rig.view();
+            ((1100.0 - view.x) * view.zoom, (600.0 - view.y) * view.zoom)
+        };
+        let before = on_screen(&rig);
+        for _ in 0..30 {
+            rig.update(1100.0, 600.0, DT);
+            let (x, y) = on_screen(&rig);
+            assert!((x - before.0).abs() < 1e-6 && (y - before.1).abs() < 1e-6, "{:?} vs {:?}", (x, y), before);
+        }
+        assert!(rig.view().zoom > 2.4);
+    }
+
+    #[test]
+    fn auto_zoom_closes_in_on_detail_and_backs_out_for_travel() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.enable_auto_zoom(1.0, 20.0, 200.0, 2.0);
+        // Small jitter around one spot
+        for i in 0..120 {
+            let jitter = if i % 2 == 0 { 5.0 } else { -5.0 };
+            rig.update(900.0 + jitter, 500.0, DT);
+        }
+        assert!(rig.view().zoom > 1.95, "zoom {}", rig.view().zoom);
+
+        // Repeated sweeps across most of the screen
+        for i in 0..180 {
+            rig.update(300.0 + (i % 60) as f64 * 22.0, 500.0, DT);
+        }
+        assert!(rig.view().zoom < 1.05, "zoom {}", rig.view().zoom);
+    }
+
+    #[test]
+    fn the_bounds_margin_keeps_the_view_off_the_edges() {
+        let mut rig = zoomed_rig();
+        rig.set_bounds_margin(100.0);
+        assert_eq!(rig.view_bounds().min_x, 580.0);
+        for _ in 0..300 {
+            rig.update(0.0, 540.0, DT);
+        }
+        assert!((rig.view().x - 580.0).abs() < 0.5, "x {}", rig.view().x);
+
+        // Too large for this zoom: the range collapses onto the center
+        rig.set_bounds_margin(10_000.0);
+        let bounds = rig.view_bounds();
+        assert_eq!((bounds.min_x, bounds.max_x, bounds.min_y, bounds.max_y), (960.0, 960.0, 540.0, 540.0));
+    }
+
+    #[test]
+    fn a_source_resize_keeps_the_framing() {
+        let mut rig = zoomed_rig();
+        rig.snap_to(600.0, 400.0);
+        rig.set_source_size(3840.0, 2160.0);
+        assert_eq!((rig.view().x, rig.view().y, rig.view().zoom), (1200.0, 800.0, 2.0));
+        let crop = rig.crop_rect();
+        assert_eq!((crop.x, crop.y, crop.width, crop.height), (240.0, 260.0, 1920.0, 1080.0));
+    }
+
+    #[test]
+    fn damping_ratio_controls_overshoot() {
+        let peak = |preset: SpringPreset| {
+            let mut rig = zoomed_rig();
+            rig.set_spring_preset(preset);
+            (0..240).fold(0.0f64, |peak, _| {
+                rig.update(1300.0, 540.0, DT);
+                peak.max(rig.view().x)
+            })
+        };
+        assert!(peak(SpringPreset::Bouncy) > 1310.0);
+        assert!(peak(SpringPreset::Gentle) <= 1300.0);
+        assert!(peak(SpringPreset::Snappy) <= 1300.0 + 1e-6);
+
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.set_spring(100.0, 0.0, 4.0);
+        rig.set_damping_ratio(1.0);
+        assert!((rig.damping_x - 40.0).abs() < 1e-9 && (rig.damping_y - 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn draw_params_trim_the_view_to_the_canvas() {
+        let rig = zoomed_rig();
+        // Same aspect: the whole view fills the canvas
+        let params = rig.draw_params(1280.0, 720.0);
+        assert_eq!((params.sx, params.sy, params.sw, params.sh), (480.0, 270.0, 960.0, 540.0));
+        assert_eq!((params.dx, params.dy, params.dw, params.dh), (0.0, 0.0, 1280.0, 720.0));
+        // Square canvas: the sides of the view are trimmed evenly
+        let params = rig.draw_params(1000.0, 1000.0);
+        assert_eq!((params.sx, params.sy, params.sw, params.sh), (690.0, 270.0, 540.0, 540.0));
+        assert_eq!((params.dx, params.dy, params.dw, params.dh), (0.0, 0.0, 1000.0, 1000.0));
+    }
+
+    #[test]
+    fn fixed_updates_do_not_depend_on_the_frame_rate() {
+        let run = |fixed: bool, fps: u32| {
+            let mut rig = zoomed_rig();
+            for _ in 0..fps {
+                if fixed {
+                    rig.update_fixed(1300.0, 700.0, 1.0 / fps as f64);
+                } else {
+                    rig.update(1300.0, 700.0, 1.0 / fps as f64);
+                }
+            }
+            rig.view()
+        };
+        let gap = |a: ViewState, b: ViewState| (a.x - b.x).hypot(a.y - b.y);
+        // After the same second of real time at 30 and 144 fps
+        let fixed_gap = gap(run(true, 30), run(true, 144));
+        let plain_gap = gap(run(false, 30), run(false, 144));
+        assert!(fixed_gap < 1e-6, "fixed {}", fixed_gap);
+        assert!(plain_gap > 100.0 * fixed_gap.max(1e-9), "plain {} vs fixed {}", plain_gap, fixed_gap);
+    }
+
+    #[test]
+    fn smoothing_lags_the_physics_and_then_catches_up() {
+        let mut plain = zoomed_rig();
+        let mut smooth = zoomed_rig();
+        smooth.set_smoothing(0.2);
+        for _ in 0..15 {
+            plain.update(1300.0, 540.0, DT);
+            smooth.update(1300.0, 540.0, DT);
+        }
+        assert!(smooth.view().x < plain.view().x - 10.0, "{} vs {}", smooth.view().x, plain.view().x);
+        for _ in 0..300 {
+            smooth.update(1300.0, 540.0, DT);
+        }
+        assert!((smooth.view().x - 1300.0).abs() < 0.5, "x {}", smooth.view().x);
+    }
+
+    #[test]
+    fn snaps_cut_without_a_pan() {
+        let mut rig = zoomed_rig();
+        for _ in 0..10 {
+            rig.update(1300.0, 700.0, DT);
+        }
+        rig.snap_to(600.0, 5000.0);
+        assert_eq!((rig.view().x, rig.view().y), (600.0, 810.0));
+        assert_eq!(rig.velocity().speed, 0.0);
+        assert!(rig.is_settled(0.5, 0.5));
+
+        // Zooming back out re-clamps the position to the center
+        rig.snap_zoom(1.0);
+        assert_eq!((rig.view().x, rig.view().y, rig.view().zoom), (960.0, 540.0, 1.0));
+    }
+
+    #[test]
+    fn new_at_starts_at_the_clamped_pose() {
+        let rig = CameraRig::new_at(1920.0, 1080.0, 600.0, 400.0, 2.0);
+        assert_eq!((rig.view().x, rig.view().y, rig.view().zoom), (600.0, 400.0, 2.0));
+        let rig = CameraRig::new_at(1920.0, 1080.0, 0.0, 2000.0, 9.0);
+        assert_eq!((rig.view().x, rig.view().y, rig.view().zoom), (384.0, 864.0, MAX_ZOOM));
+    }
+
+    #[test]
+    fn locked_axes_ignore_the_target() {
+        let mut rig = zoomed_rig();
+        rig.set_axis_lock(true, false);
+        for _ in 0..300 {
+            rig.update(300.0, 800.0, DT);
+            assert_eq!(rig.view().x, 960.0);
+        }
+        assert!((rig.view().y - 800.0).abs() < 0.5, "y {}", rig.view().y);
+
+        // A lock position applies at once, clamped to the bounds
+        rig.set_axis_lock_at(Some(600.0), Some(0.0));
+        assert_eq!((rig.view().x, rig.view().y), (600.0, 270.0));
+        rig.set_axis_lock_at(None, None);
+        for _ in 0..300 {
+            rig.update(1300.0, 700.0, DT);
+        }
+        assert!((rig.view().x - 1300.0).abs() < 0.5 && (rig.view().y - 700.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn output_aspect_shapes_the_crop() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.set_output_aspect(1.0);
+        let crop = rig.crop_rect();
+        assert_eq!((crop.x, crop.y, crop.width, crop.height), (420.0, 0.0, 1080.0, 1080.0));
+        let params = rig.draw_params(1000.0, 1000.0);
+        assert_eq!((params.dx, params.dy, params.dw, params.dh), (0.0, 0.0, 1000.0, 1000.0));
+
+        // Wider than the whole source: pillarboxed rather than cropped further
+        rig.set_output_aspect(4.0);
+        let crop = rig.crop_rect();
+        assert_eq!((crop.width, crop.height), (1920.0, 1080.0));
+        let params = rig.draw_params(4000.0, 1000.0);
+        assert!((params.dh - 1000.0).abs() < 1e-9 && params.dy.abs() < 1e-9);
+        assert!((params.dw - 1920.0 * 1000.0 / 1080.0).abs() < 1e-9);
+        assert!((params.dx - (4000.0 - params.dw) / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn several_targets_are_followed_by_their_weighted_centroid() {
+        let mut rig = zoomed_rig();
+        let points = [
+            TargetPoint { x: 600.0, y: 300.0, weight: 1.0 },
+            TargetPoint { x: 1400.0, y: 700.0, weight: 3.0 },
+            TargetPoint { x: 0.0, y: 0.0, weight: 0.0 },
+        ];
+        for _ in 0..300 {
+            rig.update_points(&points, DT);
+        }
+        let view = rig.view();
+        assert!((view.x - 1200.0).abs() < 0.5 && (view.y - 600.0).abs() < 0.5, "{:?}", view);
+    }
+
+    #[test]
+    fn multi_target_zoom_keeps_the_points_in_view() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.set_multi_target_zoom(Some(50.0));
+        let points = [
+            TargetPoint { x: 500.0, y: 300.0, weight: 1.0 },
+            TargetPoint { x: 1300.0, y: 700.0, weight: 1.0 },
+        ];
+        for _ in 0..300 {
+            rig.update_points(&points, DT);
+        }
+        assert!(rig.view().zoom > 2.0, "zoom {}", rig.view().zoom);
+        let crop = rig.crop_rect();
+        for point in points {
+            let inset = (point.x - crop.x)
+                .min(crop.x + crop.width - point.x)
+                .min(point.y - crop.y)
+                .min(crop.y + crop.height - point.y);
+            assert!(inset > 49.0, "{:?} only {} px inside {:?}", point, inset, crop);
+        }
+    }
+
+    #[test]
+    fn zoom_speed_sets_the_time_constant() {
+        let mut rig = CameraRig::new(1920.0, 1080.0);
+        rig.set_zoom_speed(0.5);
+        rig.set_target_zoom(2.0);
+        for _ in 0..30 {
+            rig.update(960.0, 540.0, DT);
+        }
+        // One time constant closes 1 - 1/e of the gap
+        let expected = 2.0 - (-1.0f64).exp();
+        assert!((rig.view().zoom - expected).abs() < 1e-9, "zoom {}", rig.view().zoom);
+
+        rig.set_zoom_speed(0.0);
+        rig.update(960.0, 540.0, DT);
+        assert_eq!(rig.view().zoom, 2.0);
+    }
+
+    #[test]
+    fn prime_only_applies_before_the_first_update() {
+        let mut rig = zoomed_rig();
+        rig.prime(1300.0, 700.0);
+        assert_eq!((rig.view().x, rig.view().y), (1300.0, 700.0));
+        assert_eq!(rig.velocity().speed, 0.0);
+
+        rig.update(1300.0, 700.0, DT);
+        rig.prime(600.0, 400.0);
+        assert_eq!((rig.view().x, rig.view().y), (1300.0, 700.0));
+    }
+}
@@ -0,0 +1,320 @@
+use crate::error::MuxError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum VideoCodec {
+    Avc,
+    Hevc,
+}
+
+// Parameter set NAL units (without length prefixes) declared by a decoder config.
+// `vps` is only populated for HEVC.
+#[derive(Clone, PartialEq)]
+pub(crate) struct ParameterSets {
+    pub(crate) vps: Vec<Vec<u8>>,
+    pub(crate) sps: Vec<Vec<u8>>,
+    pub(crate) pps: Vec<Vec<u8>>,
+}
+
+// Parse AVCC (description)
+// Format: [ver, profile, compat, level, len_size_minus_1, num_sps, (sps_len, sps)..., num_pps, (pps_len, pps)...]
+pub(crate) fn parse_avcc(description: &[u8]) -> Result<ParameterSets, MuxError> {
+    // Reads `count` length-prefixed parameter sets starting at `offset`
+    fn read_sets(description: &[u8], offset: &mut usize, count: usize, kind: &str) -> Result<Vec<Vec<u8>>, MuxError> {
+        let mut sets = Vec::with_capacity(count);
+        for i in 0..count {
+            if *offset + 2 > description.len() {
+                return Err(MuxError::AvccParse(format!("{} #{} length runs past the end", kind, i)));
+            }
+            let len = ((description[*offset] as usize) << 8) | (description[*offset + 1] as usize);
+            *offset += 2;
+            if *offset + len > description.len() {
+                return Err(MuxError::AvccParse(format!("{} #{} ({} bytes) runs past the end", kind, i, len)));
+            }
+            sets.push(description[*offset..*offset + len].to_vec());
+            *offset += len;
+        }
+        Ok(sets)
+    }
+
+    if description.len() < 7 {
+        return Err(MuxError::AvccParse(format!("description too short ({} bytes)", description.len())));
+    }
+
+    // Byte 5 is num_sps in the lower 5 bits
+    let mut offset = 6;
+    let num_sps = (description[5] & 0x1F) as usize;
+    let sps = read_sets(description, &mut offset, num_sps, "SPS")?;
+
+    if offset >= description.len() {
+        return Err(MuxError::AvccParse("missing PPS count".into()));
+    }
+    let num_pps = description[offset] as usize;
+    offset += 1;
+    let pps = read_sets(description, &mut offset, num_pps, "PPS")?;
+
+    // Writing dummy parameter sets would only produce a black video, so refuse instead
+    if sps.is_empty() || pps.is_empty() {
+        return Err(MuxError::AvccParse(format!(
+            "no SPS/PPS found in {}-byte description", description.len()
+        )));
+    }
+    Ok(ParameterSets { vps: Vec::new(), sps, pps })
+}
+
+// HEVC NAL unit types carried in hvcC arrays
+const HEVC_NAL_VPS: u8 = 32;
+const HEVC_NAL_SPS: u8 = 33;
+const HEVC_NAL_PPS: u8 = 34;
+
+// Parse HVCC (description)
+// Format: 22-byte fixed header, num_arrays, then per array:
+// [completeness|nal_type, num_nalus(u16), (nalu_len(u16), nalu)...]
+pub(crate) fn parse_hvcc(description: &[u8]) -> Result<ParameterSets, MuxError> {
+    let err = |msg: String| MuxError::AvccParse(format!("HVCC: {}", msg));
+
+    if description.len() < 23 {
+        return Err(err(format!("description too short ({} bytes)", description.len())));
+    }
+
+    let mut sets = ParameterSets { vps: Vec::new(), sps: Vec::new(), pps: Vec::new() };
+    let num_arrays = description[22] as usize;
+    let mut offset = 23;
+    for array in 0..num_arrays {
+        if offset + 3 > description.len() {
+            return Err(err(format!("array #{} header runs past the end", array)));
+        }
+        let nal_type = description[offset] & 0x3F;
+        let num_nalus = ((description[offset + 1] as usize) << 8) | (description[offset + 2] as usize);
+        offset += 3;
+
+        for i in 0..num_nalus {
+            if offset + 2 > description.len() {
+                return Err(err(format!("array #{} NALU #{} length runs past the end", array, i)));
+            }
+            let len = ((description[offset] as usize) << 8) | (description[offset + 1] as usize);
+            offset += 2;
+            if offset + len > description.len() {
+                return Err(err(format!("array #{} NALU #{} ({} bytes) runs past the end", array, i, len)));
+            }
+            let nalu = description[offset..offset + len].to_vec();
+            offset += len;
+
+            // SEI and other array types are carried in the raw record untouched
+            match nal_type {
+                HEVC_NAL_VPS => sets.vps.push(nalu),
+                HEVC_NAL_SPS => sets.sps.push(nalu),
+                HEVC_NAL_PPS => sets.pps.push(nalu),
+                _ => {}
+            }
+        }
+    }
+
+    if sets.vps.is_empty() || sets.sps.is_empty() || sets.pps.is_empty() {
+        return Err(err("missing VPS, SPS or PPS".into()));
+    }
+    Ok(sets)
+}
+
+// Exp-Golomb reader over an RBSP (a NAL payload with emulation prevention removed)
+struct BitReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl BitReader {
+    fn new(nalu: &[u8]) -> BitReader {
+        // Drop the 0x03 in every 00 00 03 sequence
+        let mut data = Vec::with_capacity(nalu.len());
+        let mut zeros = 0;
+        for &byte in nalu {
+            if zeros >= 2 && byte == 3 {
+                zeros = 0;
+                continue;
+            }
+            zeros = if byte == 0 { zeros + 1 } else { 0 };
+            data.push(byte);
+        }
+        BitReader { data, pos: 0 }
+    }
+
+    fn bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        (0..count).try_fold(0, |value, _| Some((value << 1) | self.bit()?))
+    }
+
+    fn ue(&mut self) -> Option<u32> {
+        let mut leading = 0;
+        while self.bit()? == 0 {
+            leading += 1;
+            if leading > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << leading) - 1 + self.bits(leading)?)
+    }
+
+    fn se(&mut self) -> Option<i32> {
+        let code = self.ue()?;
+        let magnitude = code.div_ceil(2) as i32;
+        Some(if code % 2 == 1 { magnitude } else { -magnitude })
+    }
+}
+
+// Display size (after cropping) coded in an H.264 SPS NAL unit, or None if it
+// can't be parsed
+pub(crate) fn avc_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(sps);
+    r.bits(8)?; // NAL header
+    let profile_idc = r.bits(8)?;
+    r.bits(16)?; // constraint flags, level_idc
+    r.ue()?; // seq_parameter_set_id
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+        chroma_format_idc = r.ue()?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.bit()? == 1;
+        }
+        r.ue()?; // bit_depth_luma_minus8
+        r.ue()?; // bit_depth_chroma_minus8
+        r.bit()?; // qpprime_y_zero_transform_bypass_flag
+        if r.bit()? == 1 {
+            // Scaling lists only need skipping over
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.bit()? == 1 {
+                    let size = if i < 6 { 16 } else { 64 };
+                    let (mut last, mut next) = (8i32, 8i32);
+                    for _ in 0..size {
+                        if next != 0 {
+                            next = (last + r.se()? + 256) % 256;
+                        }
+                        if next != 0 {
+                            last = next;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    r.ue()?; // log2_max_frame_num_minus4
+    match r.ue()? {
+        0 => {
+            r.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            r.bit()?; // delta_pic_order_always_zero_flag
+            r.se()?; // offset_for_non_ref_pic
+            r.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.ue()? {
+                r.se()?;
+            }
+        }
+        _ => {}
+    }
+    r.ue()?; // max_num_ref_frames
+    r.bit()?; // gaps_in_frame_num_value_allowed_flag
+    let width_mbs = r.ue()?.checked_add(1)?;
+    let height_map_units = r.ue()?.checked_add(1)?;
+    let frame_mbs_only = r.bit()?;
+    if frame_mbs_only == 0 {
+        r.bit()?; // mb_adaptive_frame_field_flag
+    }
+    r.bit()?; // direct_8x8_inference_flag
+
+    // Garbage can hold any size, so all arithmetic is checked
+    let mut width = width_mbs.checked_mul(16)?;
+    let mut height = height_map_units.checked_mul(16 * (2 - frame_mbs_only))?;
+    if r.bit()? == 1 {
+        let (left, right, top, bottom) = (r.ue()?, r.ue()?, r.ue()?, r.ue()?);
+        // Crop offsets count chroma samples, scaled to luma by the subsampling
+        let (crop_x, crop_y) = match (chroma_format_idc, separate_colour_plane) {
+            (0, _) | (3, true) => (1, 2 - frame_mbs_only),
+            (1, _) => (2, 2 * (2 - frame_mbs_only)),
+            (2, _) => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+        width = width.checked_sub(left.checked_add(right)?.checked_mul(crop_x)?)?;
+        height = height.checked_sub(top.checked_add(bottom)?.checked_mul(crop_y)?)?;
+    }
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: [u8; 4] = [0x67, 0x64, 0, 0x1f];
+    const PPS: [u8; 2] = [0x68, 0xee];
+
+    fn avcc(sps: &[u8], pps: &[u8]) -> Vec<u8> {
+        let mut description = vec![1, 0x64, 0, 0x1f, 0xff, 0xe1, 0, sps.len() as u8];
+        description.extend_from_slice(sps);
+        description.extend_from_slice(&[1, 0, pps.len() as u8]);
+        description.extend_from_slice(pps);
+        description
+    }
+
+    #[test]
+    fn parses_avcc_parameter_sets() {
+        let sets = parse_avcc(&avcc(&SPS, &PPS)).unwrap();
+        assert_eq!(sets.sps, vec![SPS.to_vec()]);
+        assert_eq!(sets.pps, vec![PPS.to_vec()]);
+        assert!(sets.vps.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_or_empty_avcc() {
+        let description = avcc(&SPS, &PPS);
+        for len in 0..description.len() {
+            assert!(parse_avcc(&description[..len]).is_err(), "accepted {} bytes", len);
+        }
+        let mut no_pps = avcc(&SPS, &PPS)[..12].to_vec();
+        no_pps.push(0);
+        assert!(parse_avcc(&no_pps).is_err());
+    }
+
+    #[test]
+    fn parses_hvcc_arrays() {
+        let mut description = vec![0; 22];
+        description.push(3);
+        for (nal_type, nalu) in [(HEVC_NAL_VPS, &[0x40, 1][..]), (HEVC_NAL_SPS, &[0x42, 1, 2]), (HEVC_NAL_PPS, &[0x44, 1])] {
+            description.extend_from_slice(&[0x80 | nal_type, 0, 1, 0, nalu.len() as u8]);
+            description.extend_from_slice(nalu);
+        }
+        let sets = parse_hvcc(&description).unwrap();
+        assert_eq!((sets.vps[0].len(), sets.sps[0].len(), sets.pps[0].len()), (2, 3, 2));
+        assert!(parse_hvcc(&description[..description.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn bit_reader_skips_emulation_prevention() {
+        let mut r = BitReader::new(&[0, 0, 3, 1, 0b1010_0000]);
+        assert_eq!(r.bits(24), Some(1));
+        // ue(v) codes 0 and 1, then se(v) code 3 (+2)
+        let mut r = BitReader::new(&[0b1010_0010, 0]);
+        assert_eq!((r.ue(), r.ue(), r.se()), (Some(0), Some(1), Some(2)));
+    }
+
+    #[test]
+    fn reads_cropped_sps_dimensions() {
+        // x264 1920x1080 High profile: 1088 coded lines cropped by 8
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0xC0, 0x44, 0x00,
+            0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00, 0xF0, 0x3C, 0x60, 0xC6, 0x58,
+        ];
+        assert_eq!(avc_sps_dimensions(&sps), Some((1920, 1080)));
+        assert_eq!(avc_sps_dimensions(&sps[..6]), None);
+    }
+}
@@ -0,0 +1,60 @@
+#[derive(Debug)]
+pub enum MuxError {
+    // The builder was given a configuration the muxer can't use
+    InvalidConfig(String),
+    // The AVCC description didn't contain a usable SPS and PPS
+    AvccParse(String),
+    // The underlying mp4 writer failed during the named step
+    Mp4 { step: &'static str, source: mp4::Error },
+    // A delta frame arrived before the first keyframe
+    MissingKeyframe,
+    // The output sink rejected a chunk
+    Sink(String),
+    // `finish` was called before any video frame was added
+    NoFrames,
+}
+
+impl std::fmt::Display for MuxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MuxError::InvalidConfig(msg) => write!(f, "Invalid muxer config: {}", msg),
+            MuxError::AvccParse(msg) => write!(f, "AVCC parse failed: {}", msg),
+            MuxError::Mp4 { step, source } => write!(f, "Mp4Muxer {} failed: {}", step, source),
+            MuxError::MissingKeyframe => write!(f, "The first video frame must be a keyframe"),
+            MuxError::Sink(msg) => write!(f, "Output sink failed: {}", msg),
+            MuxError::NoFrames => write!(f, "The recording has no video frames"),
+        }
+    }
+}
+
+impl std::error::Error for MuxError {}
+
+#[cfg(feature = "wasm")]
+impl From<MuxError> for wasm_bindgen::JsValue {
+    fn from(err: MuxError) -> wasm_bindgen::JsValue {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
+
+#[derive(Debug)]
+pub enum CameraError {
+    // CameraRigBuilder was given values that can't be used together
+    InvalidConfig(String),
+}
+
+impl std::fmt::Display for CameraError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CameraError::InvalidConfig(msg) => write!(f, "Invalid camera config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CameraError {}
+
+#[cfg(feature = "wasm")]
+impl From<CameraError> for wasm_bindgen::JsValue {
+    fn from(err: CameraError) -> wasm_bindgen::JsValue {
+        js_sys::Error::new(&err.to_string()).into()
+    }
+}
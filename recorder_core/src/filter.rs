@@ -0,0 +1,66 @@
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+// One-Euro filter (Casiez et al. 2012): a low-pass whose cutoff rises with the
+// signal's speed, so slow movement is smoothed hard and fast movement keeps
+// its latency low. Run one per axis on raw cursor samples before they reach
+// the camera.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug)]
+pub struct OneEuroFilter {
+    // Cutoff (Hz) at rest; lower removes more jitter
+    min_cutoff: f64,
+    // How much the cutoff grows per unit of speed; higher reduces lag
+    beta: f64,
+    // Cutoff (Hz) for the speed estimate itself
+    d_cutoff: f64,
+    // (timestamp, filtered value, filtered speed) of the previous sample
+    prev: Option<(f64, f64, f64)>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl OneEuroFilter {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(min_cutoff: f64, beta: f64, d_cutoff: f64) -> OneEuroFilter {
+        OneEuroFilter {
+            min_cutoff: min_cutoff.max(f64::MIN_POSITIVE),
+            beta: beta.max(0.0),
+            d_cutoff: d_cutoff.max(f64::MIN_POSITIVE),
+            prev: None,
+        }
+    }
+
+    // Filters `x` sampled at `timestamp` seconds. Samples that don't move
+    // time forward return the previous output unchanged.
+    pub fn filter(&mut self, x: f64, timestamp: f64) -> f64 {
+        let Some((prev_t, prev_x, prev_dx)) = self.prev else {
+            self.prev = Some((timestamp, x, 0.0));
+            return x;
+        };
+        let dt = timestamp - prev_t;
+        if !dt.is_finite() || dt <= 0.0 {
+            return prev_x;
+        }
+
+        let dx = smooth(prev_dx, (x - prev_x) / dt, alpha(self.d_cutoff, dt));
+        let cutoff = self.min_cutoff + self.beta * dx.abs();
+        let filtered = smooth(prev_x, x, alpha(cutoff, dt));
+        self.prev = Some((timestamp, filtered, dx));
+        filtered
+    }
+
+    // Forget history; the next sample passes through unfiltered
+    pub fn reset(&mut self) {
+        self.prev = None;
+    }
+}
+
+// Smoothing factor of a first-order low-pass at `cutoff` Hz over `dt` seconds
+fn alpha(cutoff: f64, dt: f64) -> f64 {
+    let tau = 1.0 / (2.0 * std::f64::consts::PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+fn smooth(prev: f64, x: f64, alpha: f64) -> f64 {
+    prev + alpha * (x - prev)
+}
@@ -0,0 +1,60 @@
+// Diagnostics go to the browser console, or to the callback registered with
+// `set_logger`; native builds stay silent
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Level {
+    Info,
+    Warn,
+}
+
+#[cfg(feature = "wasm")]
+thread_local! {
+    static LOGGER: std::cell::RefCell<Option<js_sys::Function>> = const { std::cell::RefCell::new(None) };
+}
+
+// Route diagnostics to `callback(level, message)`, where level is "info" or
+// "warn", e.g. to drop info lines and forward warnings to telemetry.
+// Call without a callback to go back to the console.
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn set_logger(callback: Option<js_sys::Function>) {
+    LOGGER.with(|logger| *logger.borrow_mut() = callback);
+}
+
+fn emit(level: Level, message: &str) {
+    // The JS imports abort off wasm32, e.g. in `cargo test` with default features
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    {
+        let level_name = match level {
+            Level::Info => "info",
+            Level::Warn => "warn",
+        };
+        let handled = LOGGER.with(|logger| match logger.borrow().as_ref() {
+            // A throwing logger must not take the muxer down with it
+            Some(callback) => {
+                let _ = callback.call2(&JsValue::NULL, &level_name.into(), &message.into());
+                true
+            }
+            None => false,
+        });
+        if !handled {
+            match level {
+                Level::Info => web_sys::console::log_1(&message.into()),
+                Level::Warn => web_sys::console::warn_1(&message.into()),
+            }
+        }
+    }
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    let _ = (level, message);
+}
+
+pub(crate) fn info(message: &str) {
+    emit(Level::Info, message);
+}
+
+pub(crate) fn warn(message: &str) {
+    emit(Level::Warn, message);
+}
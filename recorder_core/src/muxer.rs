@@ -0,0 +1,2572 @@
+use std::io::Cursor;
+
+use serde::Serialize;
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::boxes::{
+    append_child, find_box, finalize_moov, patch_traks, rotation_matrix, write_box,
+    allow_inband_parameter_sets, poster_data_type, write_color, write_rotation, ColorInfo, MasteringDisplay, Metadata, MoovExtras,
+};
+use crate::codec::{avc_sps_dimensions, parse_avcc, parse_hvcc, ParameterSets, VideoCodec};
+use crate::error::MuxError;
+use crate::{log, nal};
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub struct Mp4Muxer {
+    // In a real app we might use a RefCell or something to handle the writer,
+    // but here we will just buffer encoded data and export it.
+    // Simplifying: The generic mp4 crate is synchronous.
+    // We will just expose a way to get the final blob.
+    
+    // Actually, storing the writer is hard because of Generics/Lifetimes in Wasm struct.
+    // We'll use a globally managed buffer or just `Vec<u8>` wrapped.
+    // Use `mp4::Mp4Writer<Cursor<Vec<u8>>>` ? We can't put generic types in wasm_bindgen struct easily.
+    // We have to wrap it in a Box<dyn ...> or specific type not visible to JS.
+    // Since `mp4` writer is generic over W: Write, we can use `Cursor<Vec<u8>>`.
+    
+    // We can't store `Mp4Writer` directly if it's not `Copy`.
+    // We will store it in a `Box`? No, struct fields must be Wasm types or pointers.
+    // We can ignore the implementation details in the struct and use methods.
+    
+    // Workaround: We hold the `Vec<u8>` content manually and maybe use a lower level approach?
+    // Or just re-create the writer? No that overwrites.
+    
+    // Correct approach for wasm-bindgen with non-copy structs:
+    // Only expose handles.
+    
+    // For this MVP, I'll try to use a simplified Approach:
+    // We rely on JS `WebCodecs` to do the heavy lifting, 
+    // and we only use Rust for `CameraRig`.
+    // The user explicitly asked for "Container/Muxing: Rust mp4 crate".
+    // So I must do it.
+    
+    // I will write the implementation to use `RefCell` or `Mutex` if needed, 
+    // but `wasm_bindgen` structs are single-threaded on JS main thread usually.
+    // But `mp4::Mp4Writer` has generics.
+    
+    // I will define a wrapper struct that is NOT exported, and the exported struct holds a pointer/box to it.
+    inner: *mut std::ffi::c_void, 
+}
+
+struct InnerMuxer {
+    output: MuxOutput,
+    video: TrackState,
+    audio: Option<TrackState>,
+    // Additional video tracks (e.g. a webcam), after audio in track id order
+    extra_video: Vec<TrackState>,
+    timescale: u32,
+    // Size of the primary video track
+    dimensions: (u32, u32),
+    extras: MoovExtras,
+    // Reject delta frames until the first video keyframe has been written
+    require_keyframe_start: bool,
+    // Receives output as it's produced instead of it being held for the caller
+    sink: Option<Sink>,
+    // Whether a streamed fMP4 has been opened with its init segment yet
+    init_emitted: bool,
+    // Output the sink refused, sent again ahead of the next chunk
+    unsent: Vec<u8>,
+    // Set in replay-buffer mode, where samples are held here instead of written
+    replay: Option<ReplayBuffer>,
+    // Payload of the first video keyframe, kept for thumbnails
+    first_keyframe: Option<bytes::Bytes>,
+    // Parameter sets the video is currently encoded with
+    active_sets: ParameterSets,
+    // Length-prefixed parameter sets put in front of every video keyframe
+    // since the last `update_parameter_sets`
+    inband_sets: Option<Vec<u8>>,
+    // Last unannounced SPS warned about, so a changed stream warns once
+    warned_sps: Option<Vec<u8>>,
+    // Decode-order bookkeeping for `add_frame_ordered`
+    ordered: OrderedInput,
+    // Most negative composition offset written to the primary video track
+    min_composition_offset: i32,
+    // Keyframe spacing of the primary video track
+    gop: GopTracker,
+    // Paused spans cut out of the timeline
+    pause: PauseState,
+    // (start timestamp, title) of each `add_chapter`, in the order added
+    chapters: Vec<(u64, String)>,
+}
+
+// Input timestamps keep running through a pause while no frames arrive, so
+// the first sample after `resume` would last the whole pause. Instead the
+// gap is measured there and subtracted from every later timestamp.
+#[derive(Default)]
+struct PauseState {
+    paused: bool,
+    // Set by `resume` until the next sample fixes the offset
+    resuming: bool,
+    // Total paused time removed so far, in timescale units
+    offset: u64,
+}
+
+impl PauseState {
+    // Output timestamp for `timestamp`, plus the offset the first sample after
+    // `resume` fixes, for `settle` once it is written; `expected` is when the
+    // track's next sample is due, one usual frame after its last
+    fn rebase(&self, timestamp: u64, expected: Option<u64>) -> (u64, Option<u64>) {
+        // A track with no samples yet can't tell how long the pause was
+        let settled = match expected {
+            Some(expected) if self.resuming => Some(self.offset.max(timestamp.saturating_sub(expected))),
+            _ => None,
+        };
+        (timestamp.saturating_sub(settled.unwrap_or(self.offset)), settled)
+    }
+
+    fn settle(&mut self, offset: Option<u64>) {
+        if let Some(offset) = offset {
+            self.offset = offset;
+            self.resuming = false;
+        }
+    }
+}
+
+// Frames between video keyframes, so an encoder that stopped emitting them
+// (leaving a file that seeks terribly) is noticed during the recording
+#[derive(Default)]
+struct GopTracker {
+    // Warn once a GOP runs longer than this many frames (0 = never)
+    warn_after: u32,
+    // Frames since (and including) the latest keyframe
+    current: u64,
+    longest: u64,
+}
+
+impl GopTracker {
+    fn observe(&mut self, is_key: bool) {
+        if is_key {
+            self.longest = self.longest.max(self.current);
+            self.current = 1;
+            return;
+        }
+        self.current += 1;
+        if self.warn_after > 0 && self.current == self.warn_after as u64 + 1 {
+            log::warn(&format!("No video keyframe for over {} frames; the file will seek poorly", self.warn_after));
+        }
+    }
+
+    // Longest run so far, counting the GOP still in progress
+    fn max_interval(&self) -> u64 {
+        self.longest.max(self.current)
+    }
+}
+
+// Recent frames from `add_frame_ordered`, to check the stream is a plausible
+// reordering: decode times strictly increase and presentation times don't repeat
+#[derive(Default)]
+struct OrderedInput {
+    last_dts: Option<u64>,
+    recent_pts: std::collections::VecDeque<u64>,
+}
+
+// Presentation times are checked for repeats across this many frames, well
+// past the reorder depth of any WebCodecs encoder
+const REORDER_WINDOW: usize = 32;
+
+impl OrderedInput {
+    // Validates a frame and returns its composition offset (pts - dts).
+    // `max_lead` bounds how far before its decode time a frame may be shown.
+    // Nothing is recorded until `accept`, so a frame that fails to write can
+    // be retried.
+    fn offset(&self, dts: u64, pts: u64, max_lead: u64) -> Result<i32, MuxError> {
+        if let Some(last) = self.last_dts.filter(|&last| dts <= last) {
+            return Err(MuxError::InvalidConfig(format!(
+                "decode timestamp {} doesn't follow {}; frames must arrive in decode order", dts, last
+            )));
+        }
+        if self.recent_pts.contains(&pts) {
+            return Err(MuxError::InvalidConfig(format!("presentation timestamp {} is used twice", pts)));
+        }
+        if dts.saturating_sub(pts) > max_lead {
+            return Err(MuxError::InvalidConfig(format!(
+                "frame is presented at {}, too long before it decodes at {}", pts, dts
+            )));
+        }
+        i32::try_from(pts as i128 - dts as i128).map_err(|_| {
+            MuxError::InvalidConfig(format!("composition offset {} - {} is out of range", pts, dts))
+        })
+    }
+
+    // Records a frame that `offset` passed and that was written
+    fn accept(&mut self, dts: u64, pts: u64) {
+        self.last_dts = Some(dts);
+        if self.recent_pts.len() == REORDER_WINDOW {
+            self.recent_pts.pop_front();
+        }
+        self.recent_pts.push_back(pts);
+    }
+}
+
+// Output callback; each chunk continues the file (or fMP4 stream) where the
+// previous one ended
+type Sink = Box<dyn FnMut(&[u8]) -> Result<(), MuxError>>;
+
+// Per-track timeline bookkeeping, shared with the WebM muxer
+pub(crate) struct TrackState {
+    track_id: u32,
+    // A sample's duration is the gap to the *next* one, so the latest sample is
+    // held back until that timestamp arrives (or `finish` flushes it)
+    pending: Option<mp4::Mp4Sample>,
+    last_timestamp: u64,
+    last_duration: Option<u32>,
+    default_duration: u32,
+    // Totals over every sample pushed, including the pending one
+    sample_count: u64,
+    bytes: u64,
+    // Summed durations of the samples already released
+    duration: u64,
+    // How often each released duration occurred, for the median
+    duration_counts: std::collections::BTreeMap<u32, u64>,
+    // Set by the caller for the last sample, which has no successor to measure
+    last_sample_duration: Option<u32>,
+    // Timestamp of the latest accepted sample, to spot repeats
+    accepted_timestamp: Option<u64>,
+    // Empty or repeated samples dropped instead of written
+    skipped: u64,
+}
+
+impl TrackState {
+    pub(crate) fn new(track_id: u32, default_duration: u32) -> TrackState {
+        TrackState {
+            track_id,
+            pending: None,
+            last_timestamp: 0,
+            last_duration: None,
+            default_duration,
+            sample_count: 0,
+            bytes: 0,
+            duration: 0,
+            duration_counts: std::collections::BTreeMap::new(),
+            last_sample_duration: None,
+            accepted_timestamp: None,
+            skipped: 0,
+        }
+    }
+
+    // Empty payloads and repeats of the previous timestamp (as WebCodecs can
+    // produce around a flush) make players stumble, so they are dropped
+    fn skips(&mut self, data: &[u8], timestamp: u64) -> bool {
+        let reason = if data.is_empty() {
+            "empty sample"
+        } else if self.accepted_timestamp == Some(timestamp) {
+            "repeated timestamp"
+        } else {
+            return false;
+        };
+        self.skipped += 1;
+        log::warn(&format!("Track {}: skipping {} at {}", self.track_id, reason, timestamp));
+        true
+    }
+
+    // For muxers that lay out their own samples (WebM): the same skipping and
+    // duration bookkeeping as `write_sample`, keeping only the timing. False
+    // if the sample should be dropped.
+    pub(crate) fn accept_timing(&mut self, data: &[u8], timestamp: u64) -> bool {
+        if self.skips(data, timestamp) {
+            return false;
+        }
+        self.accepted_timestamp = Some(timestamp);
+        self.push(mp4::Mp4Sample {
+            start_time: timestamp,
+            duration: 0,
+            rendering_offset: 0,
+            is_sync: false,
+            bytes: bytes::Bytes::new(),
+        });
+        true
+    }
+
+    pub(crate) fn has_samples(&self) -> bool {
+        self.sample_count > 0
+    }
+
+    // Where the latest sample ends, its duration being `final_duration`; 0 if empty
+    pub(crate) fn end_timestamp(&self) -> u64 {
+        match self.pending {
+            Some(_) => self.last_timestamp + self.final_duration() as u64,
+            None => self.last_timestamp,
+        }
+    }
+
+    // When a sample following the last accepted one at the usual spacing
+    // would start, or None before the first
+    fn next_timestamp(&self) -> Option<u64> {
+        let spacing = self.last_duration.unwrap_or(self.default_duration);
+        self.accepted_timestamp.map(|timestamp| timestamp.saturating_add(spacing as u64))
+    }
+
+    // Duration of the pending sample once one at `timestamp` follows it. A real
+    // capture gap is kept as a long sample, so every track stays in sync; only
+    // timestamps that go backwards (or a gap too long to store) give None.
+    fn gap_to(&self, timestamp: u64) -> Option<u32> {
+        match timestamp.checked_sub(self.last_timestamp).map(u32::try_from) {
+            Some(Ok(gap)) => Some(gap.max(1)),
+            _ => None,
+        }
+    }
+
+    // The pending sample as `push` releases it when one at `timestamp` follows,
+    // without changing any state
+    fn release(&self, timestamp: u64) -> Option<mp4::Mp4Sample> {
+        self.pending.as_ref().map(|pending| mp4::Mp4Sample {
+            start_time: pending.start_time,
+            duration: self.gap_to(timestamp).unwrap_or(self.last_duration.unwrap_or(self.default_duration)),
+            rendering_offset: pending.rendering_offset,
+            is_sync: pending.is_sync,
+            bytes: pending.bytes.clone(),
+        })
+    }
+
+    // Queues `sample`, settling the duration of the previous one (see `release`)
+    fn push(&mut self, sample: mp4::Mp4Sample) {
+        let timestamp = sample.start_time;
+        self.sample_count += 1;
+        self.bytes += sample.bytes.len() as u64;
+        if self.pending.replace(sample).is_some() {
+            let duration = self.gap_to(timestamp).unwrap_or_else(|| {
+                log::warn(&format!(
+                    "Track {}: timestamp {} doesn't follow {}; reusing the previous frame duration",
+                    self.track_id, timestamp, self.last_timestamp
+                ));
+                self.last_duration.unwrap_or(self.default_duration)
+            });
+            self.last_duration = Some(duration);
+            self.duration += duration as u64;
+            *self.duration_counts.entry(duration).or_insert(0) += 1;
+        }
+
+        // Keep the reference point monotonic so one late frame can't rewind the timeline
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+    }
+
+    // The held-back last sample, lasting as long as `final_duration`
+    fn flush(&mut self) -> Option<mp4::Mp4Sample> {
+        let duration = self.final_duration();
+        self.pending.take().map(|mut last| {
+            last.duration = duration;
+            self.duration += last.duration as u64;
+            last
+        })
+    }
+
+    // The caller's explicit duration, else the median spacing so far, else the default
+    fn final_duration(&self) -> u32 {
+        if let Some(duration) = self.last_sample_duration {
+            return duration;
+        }
+        let released: u64 = self.duration_counts.values().sum();
+        let mut seen = 0;
+        for (&duration, &count) in &self.duration_counts {
+            seen += count;
+            if seen * 2 >= released {
+                return duration;
+            }
+        }
+        self.default_duration
+    }
+
+    // Track length so far, counting the pending sample as `flush` would
+    fn total_duration(&self) -> u64 {
+        let pending = self.pending.as_ref().map_or(0, |_| self.final_duration());
+        self.duration + pending as u64
+    }
+}
+
+// Recording totals for display, from `Mp4Muxer::stats`
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct MuxStats {
+    // Video track length in seconds, as `finish` would write it
+    pub duration: f64,
+    // Video frames added to the main track
+    pub frame_count: u64,
+    // Frames added to the extra video tracks from the builder's `video_track`
+    pub extra_frame_count: u64,
+    // Encoded media bytes across all tracks, excluding container overhead
+    pub bytes: u64,
+    // Average over `duration`, in bits per second; 0 until there is a duration
+    pub bitrate: f64,
+    // Empty or duplicate samples dropped across all tracks
+    pub skipped: u64,
+    // Longest run of video frames from a keyframe up to the next, in frames
+    pub max_keyframe_interval: u64,
+}
+
+impl InnerMuxer {
+    fn tracks(&self) -> impl Iterator<Item = &TrackState> {
+        std::iter::once(&self.video)
+            .chain(self.audio.as_ref())
+            .chain(self.extra_video.iter())
+    }
+
+    fn track_mut(&mut self, track_id: u32) -> Option<&mut TrackState> {
+        std::iter::once(&mut self.video)
+            .chain(self.audio.as_mut())
+            .chain(self.extra_video.iter_mut())
+            .find(|track| track.track_id == track_id)
+    }
+
+    fn audio_track_id(&self) -> Result<u32, MuxError> {
+        self.audio.as_ref()
+            .map(|audio| audio.track_id)
+            .ok_or_else(|| MuxError::InvalidConfig("no audio track was configured".into()))
+    }
+
+    // `timestamp` is the decode time; `rendering_offset` is pts - dts. False if
+    // the sample was skipped as empty or repeated. Nothing changes unless the
+    // sample is taken, so one that is refused or fails to write can be retried.
+    fn write_sample(&mut self, track_id: u32, data: &[u8], is_key: bool, timestamp: u64, rendering_offset: i32) -> Result<bool, MuxError> {
+        let audio = self.audio.as_ref().is_some_and(|audio| audio.track_id == track_id);
+        let require_keyframe_start = self.require_keyframe_start;
+        let expected = match self.track_mut(track_id) {
+            None => return Err(MuxError::InvalidConfig(format!("no track with id {}", track_id))),
+            Some(track) => track.next_timestamp(),
+        };
+        let (timestamp, pause_offset) = self.pause.rebase(timestamp, expected);
+        if self.track_mut(track_id).expect("track id was checked above").skips(data, timestamp) {
+            return Ok(false);
+        }
+
+        let bytes = if track_id == VIDEO_TRACK_ID && is_key {
+            self.check_parameter_sets(data);
+            // After `update_parameter_sets` the new ones travel in every keyframe,
+            // so seeking or cutting to any of them decodes with the right sets
+            match self.inband_sets.as_ref() {
+                Some(sets) => {
+                    let mut with_sets = Vec::with_capacity(sets.len() + data.len());
+                    with_sets.extend_from_slice(sets);
+                    with_sets.extend_from_slice(data);
+                    bytes::Bytes::from(with_sets)
+                }
+                None => bytes::Bytes::copy_from_slice(data),
+            }
+        } else {
+            bytes::Bytes::copy_from_slice(data)
+        };
+
+        if let Some(replay) = self.replay.as_mut() {
+            replay.push(BufferedSample {
+                track_id,
+                audio,
+                bytes: bytes.clone(),
+                is_key,
+                timestamp,
+                rendering_offset,
+            }, require_keyframe_start)?;
+            self.taken(track_id, is_key, timestamp, pause_offset, &bytes);
+            return Ok(true);
+        }
+
+        let track = self.track_mut(track_id).expect("track id was checked above");
+        // A file starting on a delta frame decodes as garbage until the first IDR
+        if !audio && require_keyframe_start && !is_key && track.pending.is_none() {
+            return Err(MuxError::MissingKeyframe);
+        }
+        // The previous sample goes out now that this one fixes its duration
+        let ready = track.release(timestamp);
+        self.emit_init_segment()?;
+        if let Some(ready) = ready {
+            // With a sink, fragments are cut automatically so each starts on a keyframe
+            if let (Some(_), MuxOutput::Fragmented(fragments)) = (&self.sink, &mut self.output) {
+                if track_id == VIDEO_TRACK_ID && ready.is_sync {
+                    let fragment = fragments.take_fragment();
+                    self.emit(&fragment)?;
+                }
+            }
+            self.output.write_sample(track_id, &ready)?;
+        }
+
+        // We need to create a Sample; its duration is filled in once the next one arrives
+        self.track_mut(track_id).expect("track id was checked above").push(mp4::Mp4Sample {
+            start_time: timestamp,
+            duration: 0,
+            rendering_offset,
+            is_sync: is_key,
+            bytes: bytes.clone(),
+        });
+        if track_id == VIDEO_TRACK_ID {
+            self.min_composition_offset = self.min_composition_offset.min(rendering_offset);
+        }
+        self.taken(track_id, is_key, timestamp, pause_offset, &bytes);
+        // The drained bytes are already part of the file, so the sample counts
+        // as written even if the sink refuses them (they are kept for the next emit)
+        if let (Some(_), MuxOutput::Progressive(writer)) = (&self.sink, &mut self.output) {
+            let finished = writer.drain();
+            self.emit(&finished)?;
+        }
+        Ok(true)
+    }
+
+    // Bookkeeping for a sample that was written (or buffered for replay)
+    fn taken(&mut self, track_id: u32, is_key: bool, timestamp: u64, pause_offset: Option<u64>, bytes: &bytes::Bytes) {
+        self.pause.settle(pause_offset);
+        self.track_mut(track_id).expect("track id was checked above").accepted_timestamp = Some(timestamp);
+        if track_id == VIDEO_TRACK_ID {
+            self.gop.observe(is_key);
+            if is_key && self.first_keyframe.is_none() {
+                self.first_keyframe = Some(bytes.clone());
+            }
+        }
+    }
+
+    // Warns about an SPS in `keyframe` that the decoder config doesn't know;
+    // players decode the samples after it with the wrong parameters
+    fn check_parameter_sets(&mut self, keyframe: &[u8]) {
+        let is_sps = |nalu: &[u8]| match self.extras.hvcc {
+            Some(_) => (nalu[0] >> 1) & 0x3F == 33,
+            None => nalu[0] & 0x1F == 7,
+        };
+        let unknown = nal::avcc_nalus(keyframe)
+            .filter(|nalu| !nalu.is_empty() && is_sps(nalu))
+            .find(|sps| !self.active_sets.sps.iter().any(|known| known == sps))
+            .map(<[u8]>::to_vec);
+        if unknown.is_some() && unknown != self.warned_sps {
+            log::warn("Keyframe carries an SPS that differs from the decoder config; \
+                call update_parameter_sets with the encoder's new description");
+            self.warned_sps = unknown;
+        }
+    }
+
+    // A streamed fMP4 has to open with its init segment. It goes out with the
+    // first sample rather than at build time, so `set_rotation` can still patch it.
+    fn emit_init_segment(&mut self) -> Result<(), MuxError> {
+        if self.init_emitted || self.sink.is_none() {
+            return Ok(());
+        }
+        self.init_emitted = true;
+        if let MuxOutput::Fragmented(ref fragments) = self.output {
+            let init_segment = fragments.init_segment.clone();
+            self.emit(&init_segment)?;
+        }
+        Ok(())
+    }
+
+    // Sample entry and track header settings are frozen once a frame is out
+    fn check_not_started(&self, setting: &str) -> Result<(), MuxError> {
+        if self.video.pending.is_some() || self.init_emitted {
+            return Err(MuxError::InvalidConfig(format!("{} must be set before the first frame", setting)));
+        }
+        Ok(())
+    }
+
+    // Hands `bytes` to the sink, if there is one and there's anything to hand
+    // over. Output the sink refuses is held and goes out ahead of the next
+    // chunk, so a retried call loses nothing.
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), MuxError> {
+        let Some(sink) = self.sink.as_mut() else { return Ok(()) };
+        if self.unsent.is_empty() {
+            if bytes.is_empty() {
+                return Ok(());
+            }
+            return sink(bytes).inspect_err(|_| self.unsent = bytes.to_vec());
+        }
+        self.unsent.extend_from_slice(bytes);
+        sink(&self.unsent)?;
+        self.unsent.clear();
+        Ok(())
+    }
+
+    // `progress` goes from 0.0 to 1.0 as described at `finish_with_progress_fn`
+    fn finish(mut self, progress: &mut dyn FnMut(f64)) -> Result<Vec<u8>, MuxError> {
+        progress(0.0);
+        if let Some(replay) = self.replay.as_ref() {
+            return replay.export(&self.extras, self.video.last_sample_duration, &self.chapters, progress);
+        }
+        // A track without samples makes a file players reject, so an empty
+        // recording is reported rather than written
+        if self.video.sample_count == 0 {
+            return Err(MuxError::NoFrames);
+        }
+        if self.extras.shift_composition {
+            self.extras.composition_shift = self.min_composition_offset.min(0).unsigned_abs() as u64;
+        }
+        self.emit_init_segment()?;
+        let tracks = std::iter::once(&mut self.video)
+            .chain(self.audio.as_mut())
+            .chain(self.extra_video.iter_mut());
+        for track in tracks {
+            if let Some(last) = track.flush() {
+                self.output.write_sample(track.track_id, &last)?;
+            }
+        }
+        // Numbered after every media track
+        let track_id = self.tracks().count() as u32 + 1;
+        if let (false, MuxOutput::Progressive(writer)) = (self.chapters.is_empty(), &mut self.output) {
+            let samples = chapter_samples(&self.chapters, self.video.total_duration());
+            writer.write_text_track(track_id, self.timescale, &samples)?;
+            self.extras.chapter_track = Some(track_id);
+        }
+
+        let tail = match self.output {
+            MuxOutput::Progressive(writer) => writer.finish(&self.extras, progress)?,
+            // Whatever hasn't been taken yet goes out as the last fragment
+            MuxOutput::Fragmented(mut fragments) => fragments.take_fragment(),
+        };
+        let output = match self.sink.as_mut() {
+            Some(sink) => {
+                self.unsent.extend_from_slice(&tail);
+                if !self.unsent.is_empty() {
+                    sink(&self.unsent)?;
+                }
+                Vec::new()
+            }
+            None => tail,
+        };
+        progress(1.0);
+        Ok(output)
+    }
+}
+
+// A sample as it was passed in, for the replay buffer to mux later
+struct BufferedSample {
+    track_id: u32,
+    audio: bool,
+    bytes: bytes::Bytes,
+    is_key: bool,
+    timestamp: u64,
+    rendering_offset: i32,
+}
+
+impl BufferedSample {
+    // The main video track, whose keyframes decide where a clip can start
+    fn is_primary(&self) -> bool {
+        self.track_id == VIDEO_TRACK_ID
+    }
+}
+
+// Keeps only the most recent `window` of samples, starting on a keyframe, and
+// muxes them into a standalone file on demand
+struct ReplayBuffer {
+    // Timescale units of video to keep
+    window: u64,
+    samples: std::collections::VecDeque<BufferedSample>,
+    // Settings for the muxer each clip is written with
+    config: Mp4MuxerBuilder,
+}
+
+impl ReplayBuffer {
+    fn push(&mut self, sample: BufferedSample, require_keyframe_start: bool) -> Result<(), MuxError> {
+        let has_video = self.samples.iter().any(BufferedSample::is_primary);
+        // Without a keyframe there's nothing a clip could start from
+        if require_keyframe_start && sample.is_primary() && !sample.is_key && !has_video {
+            return Err(MuxError::MissingKeyframe);
+        }
+        // Other tracks ahead of the first video frame could never make it into a clip
+        if !sample.is_primary() && !has_video {
+            return Ok(());
+        }
+
+        let is_primary = sample.is_primary();
+        let timestamp = sample.timestamp;
+        self.samples.push_back(sample);
+        if !is_primary {
+            return Ok(());
+        }
+
+        // Drop everything before the newest keyframe that still lets the clip
+        // cover the whole window; frames after it depend on it
+        let cutoff = timestamp.saturating_sub(self.window);
+        let start = self.samples.iter()
+            .rposition(|s| s.is_primary() && s.is_key && s.timestamp <= cutoff);
+        if let Some(start) = start {
+            self.samples.drain(..start);
+        }
+        Ok(())
+    }
+
+    // The retained samples as a complete progressive mp4 starting at time zero
+    fn export(
+        &self,
+        extras: &MoovExtras,
+        last_frame_duration: Option<u32>,
+        chapters: &[(u64, String)],
+        progress: &mut dyn FnMut(f64),
+    ) -> Result<Vec<u8>, MuxError> {
+        let mut clip = self.config.clip_config().build_inner()?;
+        clip.video.last_sample_duration = last_frame_duration;
+        clip.extras.rotation = extras.rotation;
+        // Includes what was set after building, like the poster
+        clip.extras.metadata = extras.metadata.clone();
+        clip.extras.start_trim = extras.start_trim;
+        clip.extras.color = extras.color;
+        clip.extras.mastering_display = extras.mastering_display;
+        clip.extras.inband_parameter_sets = extras.inband_parameter_sets;
+        clip.extras.shift_composition = extras.shift_composition;
+
+        let Some(base) = self.samples.iter().find(|s| s.is_primary()).map(|s| s.timestamp) else {
+            return clip.finish(progress);
+        };
+        // Chapters move with the video; the one already running at the start opens the clip
+        let opening = chapters.iter().filter(|(start, _)| *start <= base).max_by_key(|(start, _)| *start);
+        clip.chapters = opening.into_iter()
+            .chain(chapters.iter().filter(|(start, _)| *start > base))
+            .map(|(start, title)| (start.saturating_sub(base), title.clone()))
+            .collect();
+        // Secondary video tracks join the clip at their own first keyframe
+        let mut started = vec![VIDEO_TRACK_ID];
+        for sample in &self.samples {
+            if !sample.audio && !started.contains(&sample.track_id) {
+                if !sample.is_key {
+                    continue;
+                }
+                started.push(sample.track_id);
+            }
+            // Samples from before the first kept video frame have no picture to go with
+            let Some(timestamp) = sample.timestamp.checked_sub(base) else { continue };
+            clip.write_sample(sample.track_id, &sample.bytes, sample.is_key, timestamp, sample.rendering_offset)?;
+        }
+        clip.finish(progress)
+    }
+}
+
+enum MuxOutput {
+    // Classic mp4: media data then moov, drainable while recording
+    Progressive(ProgressiveWriter),
+    // fMP4: an init segment up front, then moof+mdat fragments on demand
+    Fragmented(FragmentWriter),
+}
+
+impl MuxOutput {
+    fn write_sample(&mut self, track_id: u32, sample: &mp4::Mp4Sample) -> Result<(), MuxError> {
+        match self {
+            MuxOutput::Progressive(writer) => writer.write_sample(track_id, sample),
+            MuxOutput::Fragmented(fragments) => {
+                fragments.push(track_id, sample);
+                Ok(())
+            }
+        }
+    }
+}
+
+// Media data is grouped into mdat boxes of roughly this size; a closed box never
+// changes again, so it can be drained while the recording is still running
+const MDAT_BLOCK_BYTES: usize = 1 << 20;
+
+// Where one track's samples ended up in the file
+#[derive(Default)]
+struct ChunkTable {
+    sample_sizes: Vec<u32>,
+    // (file offset, sample count) of each run of consecutive samples
+    chunks: Vec<(u64, u32)>,
+}
+
+// Progressive mp4 whose bytes never change once written. The mp4 crate's writer
+// only builds the sample tables (it is fed empty samples); the media data is laid
+// out here as a series of self-contained mdat boxes, and `finish` points the
+// tables at it. The layout only depends on the samples, so concatenating every
+// `drain` and the `finish` tail gives the same file as a single `finish`.
+struct ProgressiveWriter {
+    tables: mp4::Mp4Writer<Cursor<Vec<u8>>>,
+    // Finished bytes not handed out yet: the ftyp, then every closed mdat
+    ready: Vec<u8>,
+    // File offset of `ready[0]`
+    drained: u64,
+    // Payload of the mdat currently being filled
+    block: Vec<u8>,
+    // Indexed by track_id - 1
+    tracks: Vec<ChunkTable>,
+    last_track: u32,
+    // Put the moov right after the ftyp, so players can start before the end
+    faststart: bool,
+}
+
+impl ProgressiveWriter {
+    // `tables` is the writer `write_start` returned for `config`
+    fn new(tables: mp4::Mp4Writer<Cursor<Vec<u8>>>, config: &mp4::Mp4Config, faststart: bool) -> Result<ProgressiveWriter, MuxError> {
+        use mp4::WriteBox;
+
+        // The same ftyp write_start put in front of its own (unused) output
+        let mut ready = Vec::new();
+        mp4::FtypBox {
+            major_brand: config.major_brand,
+            minor_version: config.minor_version,
+            compatible_brands: config.compatible_brands.clone(),
+        }.write_box(&mut ready).map_err(|source| MuxError::Mp4 { step: "write start", source })?;
+
+        Ok(ProgressiveWriter {
+            tables,
+            drained: 0,
+            ready,
+            block: Vec::new(),
+            tracks: Vec::new(),
+            last_track: 0,
+            faststart,
+        })
+    }
+
+    fn write_sample(&mut self, track_id: u32, sample: &mp4::Mp4Sample) -> Result<(), MuxError> {
+        // Durations, sync flags and offsets go to the crate; only the size of the
+        // data matters to it, and that is overwritten in `finish`
+        let table_sample = mp4::Mp4Sample {
+            start_time: sample.start_time,
+            duration: sample.duration,
+            rendering_offset: sample.rendering_offset,
+            is_sync: sample.is_sync,
+            bytes: bytes::Bytes::new(),
+        };
+        self.tables.write_sample(track_id, &table_sample)
+            .map_err(|source| MuxError::Mp4 { step: "sample write", source })?;
+
+        let index = track_id as usize - 1;
+        if self.tracks.len() <= index {
+            self.tracks.resize_with(index + 1, ChunkTable::default);
+        }
+        let table = &mut self.tracks[index];
+
+        // The open block is written right after everything already finished
+        let offset = self.drained + self.ready.len() as u64 + 8 + self.block.len() as u64;
+        match table.chunks.last_mut() {
+            // Still contiguous with this track's previous sample
+            Some((_, count)) if self.last_track == track_id && !self.block.is_empty() => *count += 1,
+            _ => table.chunks.push((offset, 1)),
+        }
+        table.sample_sizes.push(sample.bytes.len() as u32);
+        self.block.extend_from_slice(&sample.bytes);
+        self.last_track = track_id;
+
+        if self.block.len() >= MDAT_BLOCK_BYTES {
+            self.close_block();
+        }
+        Ok(())
+    }
+
+    // Adds a text track after the media tracks, as `track_id`, holding `samples`
+    fn write_text_track(&mut self, track_id: u32, timescale: u32, samples: &[mp4::Mp4Sample]) -> Result<(), MuxError> {
+        self.tables.add_track(&mp4::TrackConfig {
+            track_type: mp4::TrackType::Subtitle,
+            timescale,
+            language: String::from("und"),
+            media_conf: mp4::MediaConfig::TtxtConfig(mp4::TtxtConfig::default()),
+        }).map_err(|source| MuxError::Mp4 { step: "chapter track add", source })?;
+        for sample in samples {
+            self.write_sample(track_id, sample)?;
+        }
+        Ok(())
+    }
+
+    fn close_block(&mut self) {
+        if self.block.is_empty() {
+            return;
+        }
+        write_box(&mut self.ready, b"mdat", |mdat| mdat.extend_from_slice(&self.block));
+        self.block.clear();
+    }
+
+    // Everything finished since the previous drain
+    fn drain(&mut self) -> Vec<u8> {
+        self.drained += self.ready.len() as u64;
+        std::mem::take(&mut self.ready)
+    }
+
+    // The undrained remainder of the file, ending with the moov (or, with
+    // faststart, the whole file with the moov in front of the media)
+    fn finish(mut self, extras: &MoovExtras, progress: &mut dyn FnMut(f64)) -> Result<Vec<u8>, MuxError> {
+        self.close_block();
+        // The crate writes its moov in one call; the table work after that is
+        // reported as it goes
+        self.tables.write_end()
+            .map_err(|source| MuxError::Mp4 { step: "finalize", source })?;
+        progress(WRITE_END_PROGRESS);
+
+        let written = self.tables.into_writer().into_inner();
+        let moov = find_box(&written, &[b"moov"])?;
+        let written_moov = &written[moov.start..moov.start + moov.size];
+        let tracks = self.tracks;
+        // Faststart normally builds the moov twice (see below)
+        let samples: u64 = tracks.iter().map(|table| table.sample_sizes.len() as u64).sum();
+        let passes = if self.faststart { 2 } else { 1 };
+        let mut reporter = TableProgress::new(progress, samples * passes);
+        // The final moov with every chunk offset moved `shift` bytes later
+        let mut build_moov = |shift: u64| {
+            let mut moov = written_moov.to_vec();
+            point_tables(&mut moov, &tracks, shift, extras.composition_shift, &mut |done| reporter.advance(done))?;
+            finalize_moov(&mut moov, extras)?;
+            Ok::<_, MuxError>(moov)
+        };
+
+        if !self.faststart {
+            let mut tail = self.ready;
+            tail.extend_from_slice(&build_moov(0)?);
+            return Ok(tail);
+        }
+
+        // Inserting the moov moves the media back by its size, which only
+        // changes that size if offsets outgrow 32 bits and need a co64
+        let mut moov = build_moov(0)?;
+        loop {
+            let shifted = build_moov(moov.len() as u64)?;
+            let settled = shifted.len() == moov.len();
+            moov = shifted;
+            if settled {
+                break;
+            }
+        }
+        let ftyp = find_box(&self.ready, &[b"ftyp"])?;
+        let media_start = ftyp.start + ftyp.size;
+        let mut file = Vec::with_capacity(self.ready.len() + moov.len());
+        file.extend_from_slice(&self.ready[..media_start]);
+        file.extend_from_slice(&moov);
+        file.extend_from_slice(&self.ready[media_start..]);
+        Ok(file)
+    }
+}
+
+// Share of `finish` progress reached once the mp4 crate has written its moov;
+// the sample table work fills the rest up to TABLES_DONE_PROGRESS
+const WRITE_END_PROGRESS: f64 = 0.1;
+const TABLES_DONE_PROGRESS: f64 = 0.95;
+// Smallest progress step worth a callback, so huge tables don't call back
+// for every sample
+const PROGRESS_STEP: f64 = 0.01;
+// Samples handled between progress checks while a table is built
+const PROGRESS_CHUNK_SAMPLES: usize = 256;
+
+// Maps samples of sample table work onto the `finish` progress fraction
+struct TableProgress<'a> {
+    callback: &'a mut dyn FnMut(f64),
+    total: u64,
+    done: u64,
+    reported: f64,
+}
+
+impl<'a> TableProgress<'a> {
+    fn new(callback: &'a mut dyn FnMut(f64), total: u64) -> TableProgress<'a> {
+        TableProgress { callback, total: total.max(1), done: 0, reported: WRITE_END_PROGRESS }
+    }
+
+    // Records `samples` more handled, calling back once the fraction has moved
+    // on by PROGRESS_STEP (or the work is complete). A pass beyond the expected
+    // ones adds nothing.
+    fn advance(&mut self, samples: u64) {
+        self.done = (self.done + samples).min(self.total);
+        let fraction = WRITE_END_PROGRESS
+            + (TABLES_DONE_PROGRESS - WRITE_END_PROGRESS) * self.done as f64 / self.total as f64;
+        let complete = self.done == self.total && fraction > self.reported;
+        if fraction - self.reported >= PROGRESS_STEP || complete {
+            self.reported = fraction;
+            (self.callback)(fraction);
+        }
+    }
+}
+
+// Points a moov's sample tables at where `tracks` put the media, `shift`
+// bytes further into the file than recorded, and adds `composition_shift` to
+// the primary video's composition offsets. `progress` is told how many
+// samples each step covered, one track at a time.
+fn point_tables(
+    moov: &mut Vec<u8>,
+    tracks: &[ChunkTable],
+    shift: u64,
+    composition_shift: u64,
+    progress: &mut dyn FnMut(u64),
+) -> Result<(), MuxError> {
+    patch_traks(moov, |moov| {
+        for trak in moov.traks.iter_mut() {
+            let Some(table) = tracks.get(trak.tkhd.track_id as usize - 1) else { continue };
+            let stbl = &mut trak.mdia.minf.stbl;
+
+            // Negative composition offsets need a version 1 ctts
+            if let Some(ctts) = stbl.ctts.as_mut() {
+                if trak.tkhd.track_id == VIDEO_TRACK_ID && composition_shift > 0 {
+                    let composition_shift = composition_shift.min(i32::MAX as u64) as i32;
+                    for entry in ctts.entries.iter_mut() {
+                        entry.sample_offset = entry.sample_offset.saturating_add(composition_shift);
+                    }
+                }
+                if ctts.entries.iter().any(|entry| entry.sample_offset < 0) {
+                    ctts.version = 1;
+                }
+            }
+
+            stbl.stsz.sample_size = 0;
+            stbl.stsz.sample_count = table.sample_sizes.len() as u32;
+            stbl.stsz.sample_sizes.clear();
+            stbl.stsz.sample_sizes.reserve(table.sample_sizes.len());
+            for sizes in table.sample_sizes.chunks(PROGRESS_CHUNK_SAMPLES) {
+                stbl.stsz.sample_sizes.extend_from_slice(sizes);
+                progress(sizes.len() as u64);
+            }
+
+            // One stsc entry per change in samples-per-chunk
+            stbl.stsc.entries.clear();
+            let mut first_sample = 1;
+            for (i, &(_, count)) in table.chunks.iter().enumerate() {
+                if stbl.stsc.entries.last().map(|e| e.samples_per_chunk) != Some(count) {
+                    stbl.stsc.entries.push(Default::default());
+                    let entry = stbl.stsc.entries.last_mut().unwrap();
+                    entry.first_chunk = i as u32 + 1;
+                    entry.samples_per_chunk = count;
+                    entry.sample_description_index = 1;
+                    entry.first_sample = first_sample;
+                }
+                first_sample += count;
+            }
+
+            let offsets = table.chunks.iter().map(|&(offset, _)| offset + shift);
+            if table.chunks.last().is_some_and(|&(offset, _)| offset + shift > u32::MAX as u64) {
+                let mut co64 = stbl.co64.take().unwrap_or_default();
+                co64.entries = offsets.collect();
+                stbl.co64 = Some(co64);
+                stbl.stco = None;
+            } else {
+                let mut stco = stbl.stco.take().unwrap_or_default();
+                stco.entries = offsets.map(|offset| offset as u32).collect();
+                stbl.stco = Some(stco);
+                stbl.co64 = None;
+            }
+
+            // The crate sized the decoder buffer from the empty samples it saw
+            if let Some(esds) = stbl.stsd.mp4a.as_mut().and_then(|mp4a| mp4a.esds.as_mut()) {
+                esds.es_desc.dec_config.buffer_size_db =
+                    table.sample_sizes.iter().copied().max().unwrap_or(0);
+            }
+        }
+    })
+}
+
+// One tx3g sample per chapter, each lasting until the next one starts and the
+// last until `end`. Text tracks have no edit list of their own, so the first
+// chapter always opens the video. A later chapter at the same start replaces
+// an earlier one, and any starting at or after `end` is dropped.
+fn chapter_samples(chapters: &[(u64, String)], end: u64) -> Vec<mp4::Mp4Sample> {
+    let mut sorted: Vec<&(u64, String)> = chapters.iter().collect();
+    sorted.sort_by_key(|(start, _)| *start);
+    let mut starts: Vec<(u64, &str)> = Vec::new();
+    for (start, title) in sorted {
+        if *start >= end {
+            log::warn(&format!("Chapter \"{}\" starts after the video ends; dropping it", title));
+            continue;
+        }
+        let start = if starts.is_empty() { 0 } else { *start };
+        match starts.last_mut() {
+            Some(last) if last.0 == start => last.1 = title,
+            _ => starts.push((start, title)),
+        }
+    }
+
+    let ends = starts.iter().skip(1).map(|&(start, _)| start).chain(std::iter::once(end));
+    starts.iter().zip(ends).map(|(&(start, title), end)| {
+        // A 16-bit length and the UTF-8 text, then the `encd` box QuickTime
+        // reads the encoding from
+        let mut text = Vec::with_capacity(title.len() + 14);
+        text.extend_from_slice(&(title.len() as u16).to_be_bytes());
+        text.extend_from_slice(title.as_bytes());
+        write_box(&mut text, b"encd", |encd| encd.extend_from_slice(&0x100u32.to_be_bytes()));
+        mp4::Mp4Sample {
+            start_time: start,
+            duration: u32::try_from(end - start).unwrap_or(u32::MAX),
+            rendering_offset: 0,
+            is_sync: true,
+            bytes: bytes::Bytes::from(text),
+        }
+    }).collect()
+}
+
+// Samples are kept in encoded form until the next `take_fragment`
+struct FragmentSample {
+    bytes: bytes::Bytes,
+    duration: u32,
+    is_sync: bool,
+    composition_offset: i32,
+}
+
+struct FragmentTrack {
+    track_id: u32,
+    // Decode time of the first sample in the next fragment
+    base_decode_time: u64,
+    samples: Vec<FragmentSample>,
+}
+
+struct FragmentWriter {
+    init_segment: Vec<u8>,
+    sequence_number: u32,
+    tracks: Vec<FragmentTrack>,
+}
+
+// trun sample_flags for sync samples (depends on nothing) and the rest
+// (depends on others, non-sync)
+const SAMPLE_FLAGS_SYNC: u32 = 0x0200_0000;
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x0101_0000;
+
+impl FragmentWriter {
+    // `finished` is the output of an Mp4Writer that was ended before any sample
+    // was written: ftyp, an empty mdat and a moov with empty sample tables.
+    fn new(finished: Vec<u8>, track_ids: &[u32]) -> Result<FragmentWriter, MuxError> {
+        let ftyp = find_box(&finished, &[b"ftyp"])?;
+        let moov = find_box(&finished, &[b"moov"])?;
+
+        let mut init_segment = finished[ftyp.start..ftyp.start + ftyp.size].to_vec();
+        init_segment.extend_from_slice(&finished[moov.start..moov.start + moov.size]);
+
+        // mvex announces that the samples live in movie fragments
+        let mut mvex = Vec::new();
+        write_box(&mut mvex, b"mvex", |mvex| {
+            for &track_id in track_ids {
+                write_box(mvex, b"trex", |trex| {
+                    trex.extend_from_slice(&[0, 0, 0, 0]); // version + flags
+                    trex.extend_from_slice(&track_id.to_be_bytes());
+                    trex.extend_from_slice(&1u32.to_be_bytes()); // default_sample_description_index
+                    trex.extend_from_slice(&[0; 12]); // default duration, size, flags
+                });
+            }
+        });
+        append_child(&mut init_segment, &[b"moov"], &mvex)?;
+
+        Ok(FragmentWriter {
+            init_segment,
+            sequence_number: 0,
+            tracks: track_ids
+                .iter()
+                .map(|&track_id| FragmentTrack { track_id, base_decode_time: 0, samples: Vec::new() })
+                .collect(),
+        })
+    }
+
+    fn push(&mut self, track_id: u32, sample: &mp4::Mp4Sample) {
+        if let Some(track) = self.tracks.iter_mut().find(|t| t.track_id == track_id) {
+            track.samples.push(FragmentSample {
+                bytes: sample.bytes.clone(),
+                duration: sample.duration,
+                is_sync: sample.is_sync,
+                composition_offset: sample.rendering_offset,
+            });
+        }
+    }
+
+    // Serializes everything pushed since the last call as one moof+mdat pair
+    fn take_fragment(&mut self) -> Vec<u8> {
+        if self.tracks.iter().all(|t| t.samples.is_empty()) {
+            return Vec::new();
+        }
+        self.sequence_number += 1;
+
+        let mut out = Vec::new();
+        // (position of a trun data_offset field, offset of its data within the mdat payload)
+        let mut data_offsets = Vec::new();
+        let mut data_len = 0usize;
+        let sequence_number = self.sequence_number;
+        write_box(&mut out, b"moof", |moof| {
+            write_box(moof, b"mfhd", |mfhd| {
+                mfhd.extend_from_slice(&[0, 0, 0, 0]);
+                mfhd.extend_from_slice(&sequence_number.to_be_bytes());
+            });
+            for track in self.tracks.iter().filter(|t| !t.samples.is_empty()) {
+                write_box(moof, b"traf", |traf| {
+                    // default-base-is-moof: data offsets are relative to the moof start
+                    write_box(traf, b"tfhd", |tfhd| {
+                        tfhd.extend_from_slice(&[0, 0x02, 0, 0]);
+                        tfhd.extend_from_slice(&track.track_id.to_be_bytes());
+                    });
+                    write_box(traf, b"tfdt", |tfdt| {
+                        tfdt.extend_from_slice(&[1, 0, 0, 0]);
+                        tfdt.extend_from_slice(&track.base_decode_time.to_be_bytes());
+                    });
+                    // data-offset, sample-duration, sample-size and sample-flags present,
+                    // plus (signed, hence version 1) composition offsets once B-frames show up
+                    let reordered = track.samples.iter().any(|s| s.composition_offset != 0);
+                    write_box(traf, b"trun", |trun| {
+                        if reordered {
+                            trun.extend_from_slice(&[1, 0, 0x0F, 0x01]);
+                        } else {
+                            trun.extend_from_slice(&[0, 0, 0x07, 0x01]);
+                        }
+                        trun.extend_from_slice(&(track.samples.len() as u32).to_be_bytes());
+                        data_offsets.push((trun.len(), data_len));
+                        trun.extend_from_slice(&[0; 4]);
+                        for sample in &track.samples {
+                            let flags = if sample.is_sync { SAMPLE_FLAGS_SYNC } else { SAMPLE_FLAGS_NON_SYNC };
+                            trun.extend_from_slice(&sample.duration.to_be_bytes());
+                            trun.extend_from_slice(&(sample.bytes.len() as u32).to_be_bytes());
+                            trun.extend_from_slice(&flags.to_be_bytes());
+                            if reordered {
+                                trun.extend_from_slice(&sample.composition_offset.to_be_bytes());
+                            }
+                        }
+                    });
+                });
+                data_len += track.samples.iter().map(|s| s.bytes.len()).sum::<usize>();
+            }
+        });
+
+        // Sample data starts right after the moof and the mdat header
+        let moof_size = out.len();
+        for (field, offset) in data_offsets {
+            let data_offset = (moof_size + 8 + offset) as u32;
+            out[field..field + 4].copy_from_slice(&data_offset.to_be_bytes());
+        }
+
+        write_box(&mut out, b"mdat", |mdat| {
+            for track in self.tracks.iter_mut() {
+                for sample in track.samples.drain(..) {
+                    track.base_decode_time += sample.duration as u64;
+                    mdat.extend_from_slice(&sample.bytes);
+                }
+            }
+        });
+        out
+    }
+}
+
+// AAC parameters for the optional audio track
+#[derive(Clone)]
+struct AudioConfig {
+    sample_rate: u32,
+    channel_count: u32,
+    audio_specific_config: Vec<u8>,
+}
+
+impl AudioConfig {
+    // Prefer the AudioSpecificConfig bits, falling back to the explicit rate/channels
+    fn to_aac_config(&self) -> Result<mp4::AacConfig, MuxError> {
+        let asc = &self.audio_specific_config;
+        let (object_type, freq_index, channels) = if asc.len() >= 2 {
+            (asc[0] >> 3, ((asc[0] & 0x07) << 1) | (asc[1] >> 7), (asc[1] >> 3) & 0x0F)
+        } else {
+            // AAC-LC with the index looked up below
+            (2, 0x0F, self.channel_count as u8)
+        };
+
+        // Index 0xF means the rate is written out explicitly; use the caller's instead
+        let freq_index = if freq_index == 0x0F {
+            (0u8..=0x0C)
+                .find(|&i| {
+                    mp4::SampleFreqIndex::try_from(i).is_ok_and(|f| f.freq() == self.sample_rate)
+                })
+                .ok_or_else(|| {
+                    MuxError::InvalidConfig(format!("unsupported AAC sample rate {}", self.sample_rate))
+                })?
+        } else {
+            freq_index
+        };
+
+        let invalid = |source| MuxError::Mp4 { step: "audio config", source };
+        Ok(mp4::AacConfig {
+            bitrate: 0,
+            profile: mp4::AudioObjectType::try_from(object_type).map_err(invalid)?,
+            freq_index: mp4::SampleFreqIndex::try_from(freq_index).map_err(invalid)?,
+            chan_conf: mp4::ChannelConfig::try_from(channels).map_err(invalid)?,
+        })
+    }
+}
+
+// The main video track; audio and extra video tracks follow it
+const VIDEO_TRACK_ID: u32 = 1;
+// Timescale matching WebCodecs' microsecond VideoFrame timestamps
+const DEFAULT_TIMESCALE: u32 = 1_000_000;
+// How far ahead of its decode time `add_frame_ordered` lets a frame be shown
+const MAX_PRESENTATION_LEAD_SECS: u64 = 1;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub struct Mp4MuxerBuilder {
+    width: u32,
+    height: u32,
+    codec: VideoCodec,
+    description: Vec<u8>,
+    timescale: u32,
+    // Duration of the first video frame, before any spacing has been observed
+    default_frame_duration: Option<u32>,
+    audio: Option<AudioConfig>,
+    fragmented: bool,
+    faststart: bool,
+    major_brand: String,
+    metadata: Metadata,
+    require_keyframe_start: bool,
+    sink: Option<Sink>,
+    // Seconds of replay buffer to keep, if in replay-buffer mode
+    replay_window: Option<f64>,
+    extra_video: Vec<VideoTrackConfig>,
+    // Warn when video keyframes are further apart than this many frames (0 = never)
+    max_keyframe_interval: u32,
+    rotation: u16,
+    color: Option<ColorInfo>,
+}
+
+// An additional AVC video track, e.g. a webcam recorded alongside the screen
+#[derive(Clone)]
+struct VideoTrackConfig {
+    width: u32,
+    height: u32,
+    description: Vec<u8>,
+}
+
+impl Default for Mp4MuxerBuilder {
+    fn default() -> Self {
+        Mp4MuxerBuilder {
+            width: 0,
+            height: 0,
+            codec: VideoCodec::Avc,
+            description: Vec::new(),
+            timescale: DEFAULT_TIMESCALE,
+            default_frame_duration: None,
+            audio: None,
+            fragmented: false,
+            faststart: false,
+            major_brand: String::from("isom"),
+            metadata: Metadata::default(),
+            require_keyframe_start: true,
+            sink: None,
+            replay_window: None,
+            extra_video: Vec::new(),
+            max_keyframe_interval: 0,
+            rotation: 0,
+            color: None,
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Mp4MuxerBuilder {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new() -> Mp4MuxerBuilder {
+        Mp4MuxerBuilder::default()
+    }
+
+    // Leave (or set to 0) `width` or `height` to use the size coded in the SPS
+    pub fn width(mut self, width: u32) -> Mp4MuxerBuilder {
+        self.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: u32) -> Mp4MuxerBuilder {
+        self.height = height;
+        self
+    }
+
+    // AVCDecoderConfigurationRecord, i.e. WebCodecs' `decoderConfig.description`
+    pub fn avcc(mut self, description: &[u8]) -> Mp4MuxerBuilder {
+        self.codec = VideoCodec::Avc;
+        self.description = description.to_vec();
+        self
+    }
+
+    // HEVCDecoderConfigurationRecord for `hvc1` streams; replaces any AVCC
+    pub fn hvcc(mut self, description: &[u8]) -> Mp4MuxerBuilder {
+        self.codec = VideoCodec::Hevc;
+        self.description = description.to_vec();
+        self
+    }
+
+    // Units per second for sample timestamps passed to `add_frame`
+    pub fn timescale(mut self, timescale: u32) -> Mp4MuxerBuilder {
+        self.timescale = timescale;
+        self
+    }
+
+    // Duration (in timescale units) assumed for a video frame whose successor
+    // hasn't been seen; defaults to 1/60 s
+    pub fn default_frame_duration(mut self, duration: u32) -> Mp4MuxerBuilder {
+        self.default_frame_duration = Some(duration);
+        self
+    }
+
+    // Same as `default_frame_duration`, given the nominal frame rate instead.
+    // Converted with the timescale set so far, so call `timescale` first.
+    pub fn frame_rate(mut self, fps: f64) -> Mp4MuxerBuilder {
+        self.default_frame_duration = if fps.is_finite() && fps > 0.0 {
+            Some((self.timescale as f64 / fps).round() as u32)
+        } else {
+            None
+        };
+        self
+    }
+
+    // Adds an AAC track; `audio_specific_config` is WebCodecs' audio `description`
+    pub fn audio(mut self, sample_rate: u32, channel_count: u32, audio_specific_config: &[u8]) -> Mp4MuxerBuilder {
+        self.audio = Some(AudioConfig {
+            sample_rate,
+            channel_count,
+            audio_specific_config: audio_specific_config.to_vec(),
+        });
+        self
+    }
+
+    // Adds another AVC video track with its own size and AVCC description.
+    // Extra tracks get ids after the main video (1) and audio (2, if set), in
+    // the order added; feed them with `add_frame_track`.
+    pub fn video_track(mut self, width: u32, height: u32, avcc: &[u8]) -> Mp4MuxerBuilder {
+        self.extra_video.push(VideoTrackConfig { width, height, description: avcc.to_vec() });
+        self
+    }
+
+    // Emit fragmented MP4: an init segment plus moof+mdat fragments pulled with
+    // `take_fragment`, instead of one blob from `finish`
+    pub fn fragmented(mut self, enabled: bool) -> Mp4MuxerBuilder {
+        self.fragmented = enabled;
+        self
+    }
+
+    // Progressive output with the moov ahead of the media, so web players can
+    // start and seek without fetching the end of the file first. The file is
+    // only put together in `finish`, so it can't be drained or streamed.
+    pub fn faststart(mut self, enabled: bool) -> Mp4MuxerBuilder {
+        self.faststart = enabled;
+        self
+    }
+
+    // When on (the default), delta frames before the first keyframe are
+    // rejected with an error instead of being written
+    pub fn require_keyframe_start(mut self, enabled: bool) -> Mp4MuxerBuilder {
+        self.require_keyframe_start = enabled;
+        self
+    }
+
+    // ftyp major brand, e.g. "mp42" for players that reject "isom"
+    pub fn major_brand(mut self, brand: &str) -> Mp4MuxerBuilder {
+        self.major_brand = brand.to_string();
+        self
+    }
+
+    pub fn title(mut self, title: &str) -> Mp4MuxerBuilder {
+        self.metadata.title = Some(title.to_string());
+        self
+    }
+
+    // Name of the tool that made the file
+    pub fn encoder(mut self, encoder: &str) -> Mp4MuxerBuilder {
+        self.metadata.encoder = Some(encoder.to_string());
+        self
+    }
+
+    // Recording start as seconds since the Unix epoch (`Date.now() / 1000`)
+    pub fn creation_time(mut self, unix_seconds: f64) -> Mp4MuxerBuilder {
+        self.metadata.creation_time = (unix_seconds.is_finite() && unix_seconds >= 0.0)
+            .then_some(unix_seconds as u64);
+        self
+    }
+
+    // Replay-buffer mode: frames are kept in memory, bounded to about the last
+    // `seconds`, and nothing is written until `export_clip` or `finish` muxes
+    // what's retained. The clip starts on the keyframe its first frames need,
+    // so it can run up to a GOP longer. Progressive output only.
+    pub fn replay_buffer(mut self, seconds: f64) -> Mp4MuxerBuilder {
+        self.replay_window = (seconds.is_finite() && seconds > 0.0).then_some(seconds);
+        self
+    }
+
+    // Warn (once per GOP) when more than `frames` video frames pass without a
+    // keyframe; the longest gap is in `stats` either way. 0 turns it off.
+    pub fn max_keyframe_interval(mut self, frames: u32) -> Mp4MuxerBuilder {
+        self.max_keyframe_interval = frames;
+        self
+    }
+
+    // Clockwise rotation (0, 90, 180 or 270), as in `Mp4Muxer::set_rotation`;
+    // checked by `build`
+    pub fn rotation(mut self, degrees: u16) -> Mp4MuxerBuilder {
+        self.rotation = degrees;
+        self
+    }
+
+    // Colour description, as in `Mp4Muxer::set_color_info`
+    pub fn color(mut self, primaries: u16, transfer: u16, matrix: u16, full_range: bool) -> Mp4MuxerBuilder {
+        self.color = Some(ColorInfo { primaries, transfer, matrix, full_range });
+        self
+    }
+
+    pub fn build(self) -> Result<Mp4Muxer, MuxError> {
+        let inner = Box::new(self.build_inner()?);
+        Ok(Mp4Muxer {
+            inner: Box::into_raw(inner) as *mut std::ffi::c_void,
+        })
+    }
+}
+
+impl Mp4MuxerBuilder {
+    // The settings a replay clip is muxed with: everything but the live-output options
+    fn clip_config(&self) -> Mp4MuxerBuilder {
+        Mp4MuxerBuilder {
+            width: self.width,
+            height: self.height,
+            codec: self.codec,
+            description: self.description.clone(),
+            timescale: self.timescale,
+            default_frame_duration: self.default_frame_duration,
+            audio: self.audio.clone(),
+            fragmented: false,
+            faststart: self.faststart,
+            major_brand: self.major_brand.clone(),
+            metadata: self.metadata.clone(),
+            require_keyframe_start: self.require_keyframe_start,
+            sink: None,
+            replay_window: None,
+            extra_video: self.extra_video.clone(),
+            // The live recording already warned about these frames
+            max_keyframe_interval: 0,
+            rotation: self.rotation,
+            color: self.color,
+        }
+    }
+
+    fn build_inner(self) -> Result<InnerMuxer, MuxError> {
+        // Validate everything up front so no frame is ever written with a bad config
+        let parameter_sets = match self.codec {
+            VideoCodec::Avc => parse_avcc(&self.description)?,
+            VideoCodec::Hevc => parse_hvcc(&self.description)?,
+        };
+        log::info(&format!(
+            "Parsed {} VPS, {} SPS and {} PPS",
+            parameter_sets.vps.len(), parameter_sets.sps.len(), parameter_sets.pps.len()
+        ));
+        let (width, height) = resolve_dimensions(self.width, self.height, self.codec, &parameter_sets)?;
+        rotation_matrix(self.rotation, width, height)?;
+        let mut extra_sets = Vec::new();
+        for track in &self.extra_video {
+            let sets = parse_avcc(&track.description)?;
+            let dimensions = resolve_dimensions(track.width, track.height, VideoCodec::Avc, &sets)?;
+            extra_sets.push((dimensions, sets));
+        }
+        if self.timescale == 0 {
+            return Err(MuxError::InvalidConfig("timescale must be non-zero".into()));
+        }
+        if self.default_frame_duration == Some(0) {
+            return Err(MuxError::InvalidConfig("default frame duration must be non-zero".into()));
+        }
+        if self.faststart && (self.fragmented || self.sink.is_some()) {
+            return Err(MuxError::InvalidConfig("faststart output is assembled in finish, not streamed".into()));
+        }
+        if self.replay_window.is_some() && (self.fragmented || self.sink.is_some()) {
+            return Err(MuxError::InvalidConfig("replay buffer clips are exported whole, not streamed".into()));
+        }
+        let replay = self.replay_window.map(|seconds| ReplayBuffer {
+            window: (seconds * self.timescale as f64) as u64,
+            samples: std::collections::VecDeque::new(),
+            config: self.clip_config(),
+        });
+
+        let media_conf = match self.codec {
+            VideoCodec::Avc => mp4::MediaConfig::AvcConfig(mp4::AvcConfig {
+                width: width as u16,
+                height: height as u16,
+                // The mp4 crate only takes one of each; the rest are restored in `finish`
+                seq_param_set: parameter_sets.sps[0].clone(),
+                pic_param_set: parameter_sets.pps[0].clone(),
+            }),
+            // The crate writes an empty hvcC; the real record is spliced in by `finish`
+            VideoCodec::Hevc => mp4::MediaConfig::HevcConfig(mp4::HevcConfig {
+                width: width as u16,
+                height: height as u16,
+            }),
+        };
+
+        let buffer = Vec::new();
+        let cursor = Cursor::new(buffer);
+        
+        log::info("Creating Mp4Writer...");
+        let brand: mp4::FourCC = self.major_brand.parse()
+            .map_err(|source| MuxError::Mp4 { step: "brand parse", source })?;
+        let isom = "isom".parse().map_err(|source| MuxError::Mp4 { step: "brand parse", source })?;
+        let mut compatible_brands = vec![brand];
+        if brand != isom {
+            compatible_brands.push(isom);
+        }
+        
+        let config = mp4::Mp4Config {
+            major_brand: brand,
+            minor_version: 512,
+            compatible_brands,
+            timescale: self.timescale,
+        };
+        let mut writer = mp4::Mp4Writer::write_start(cursor, &config).map_err(|source| MuxError::Mp4 { step: "write start", source })?;
+        
+        log::info("Adding track...");
+        writer.add_track(&mp4::TrackConfig {
+            track_type: mp4::TrackType::Video,
+            timescale: self.timescale,
+            language: String::from("und"),
+            media_conf,
+        }).map_err(|source| MuxError::Mp4 { step: "track add", source })?;
+
+        // ~60fps until we've seen two timestamps, unless told otherwise
+        let default_frame_duration = self.default_frame_duration.unwrap_or((self.timescale / 60).max(1));
+        let video = TrackState::new(VIDEO_TRACK_ID, default_frame_duration);
+
+        let audio = match self.audio {
+            Some(ref audio) => {
+                let aac = audio.to_aac_config()?;
+                let sample_rate = aac.freq_index.freq();
+                log::info("Adding audio track...");
+                writer.add_track(&mp4::TrackConfig {
+                    track_type: mp4::TrackType::Audio,
+                    timescale: self.timescale,
+                    language: String::from("und"),
+                    media_conf: mp4::MediaConfig::AacConfig(aac),
+                }).map_err(|source| MuxError::Mp4 { step: "audio track add", source })?;
+
+                // One AAC frame is 1024 samples
+                let frame_duration = (1024 * self.timescale as u64 / sample_rate as u64).max(1) as u32;
+                Some(TrackState::new(2, frame_duration))
+            }
+            None => None,
+        };
+
+        // Numbered after video and audio, in the order they were configured
+        let mut extra_video = Vec::new();
+        let mut extra_parameter_sets = Vec::new();
+        for ((width, height), sets) in extra_sets {
+            let track_id = VIDEO_TRACK_ID + 1 + audio.is_some() as u32 + extra_video.len() as u32;
+            log::info(&format!("Adding video track {}...", track_id));
+            writer.add_track(&mp4::TrackConfig {
+                track_type: mp4::TrackType::Video,
+                timescale: self.timescale,
+                language: String::from("und"),
+                media_conf: mp4::MediaConfig::AvcConfig(mp4::AvcConfig {
+                    width: width as u16,
+                    height: height as u16,
+                    seq_param_set: sets.sps[0].clone(),
+                    pic_param_set: sets.pps[0].clone(),
+                }),
+            }).map_err(|source| MuxError::Mp4 { step: "video track add", source })?;
+            extra_video.push(TrackState::new(track_id, default_frame_duration));
+            extra_parameter_sets.push((track_id, sets));
+        }
+
+        let extras = MoovExtras {
+            parameter_sets,
+            extra_parameter_sets,
+            hvcc: match self.codec {
+                VideoCodec::Hevc => Some(self.description),
+                VideoCodec::Avc => None,
+            },
+            metadata: self.metadata,
+            rotation: self.rotation,
+            start_trim: 0,
+            color: self.color,
+            mastering_display: None,
+            inband_parameter_sets: false,
+            shift_composition: false,
+            composition_shift: 0,
+            chapter_track: None,
+        };
+
+        let output = if self.fragmented {
+            // Ending a writer with no samples yields exactly the moov an init segment needs
+            writer.write_end()
+                .map_err(|source| MuxError::Mp4 { step: "init segment", source })?;
+            let mut finished = writer.into_writer().into_inner();
+            finalize_moov(&mut finished, &extras)?;
+
+            let mut track_ids = vec![video.track_id];
+            track_ids.extend(audio.as_ref().map(|a| a.track_id));
+            track_ids.extend(extra_video.iter().map(|t| t.track_id));
+            MuxOutput::Fragmented(FragmentWriter::new(finished, &track_ids)?)
+        } else {
+            MuxOutput::Progressive(ProgressiveWriter::new(writer, &config, self.faststart)?)
+        };
+
+        log::info("Mp4Muxer initialized");
+
+        Ok(InnerMuxer {
+            output,
+            video,
+            audio,
+            extra_video,
+            timescale: self.timescale,
+            dimensions: (width, height),
+            require_keyframe_start: self.require_keyframe_start,
+            sink: self.sink,
+            init_emitted: false,
+            unsent: Vec::new(),
+            replay,
+            first_keyframe: None,
+            active_sets: extras.parameter_sets.clone(),
+            inband_sets: None,
+            warned_sps: None,
+            ordered: OrderedInput::default(),
+            min_composition_offset: 0,
+            gop: GopTracker { warn_after: self.max_keyframe_interval, ..GopTracker::default() },
+            pause: PauseState::default(),
+            chapters: Vec::new(),
+            extras,
+        })
+    }
+}
+
+// Output sinks take closures, which wasm-bindgen can't export
+impl Mp4MuxerBuilder {
+    // Stream the output through `sink` as it's produced rather than holding it
+    // for `drain_buffer`, `take_fragment` and `finish`, which then return
+    // nothing. Progressive files arrive as the ftyp and each finished mdat,
+    // then the moov from `finish`; fragmented output starts with the init
+    // segment and is cut into a fragment at every video keyframe.
+    pub fn sink_fn(mut self, sink: impl FnMut(&[u8]) -> Result<(), MuxError> + 'static) -> Mp4MuxerBuilder {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+impl Mp4MuxerBuilder {
+    // `sink_fn` for JS: `sink` is called with a Uint8Array per chunk. An
+    // exception thrown by it fails the add_frame or finish that produced the chunk.
+    pub fn sink(self, sink: js_sys::Function) -> Mp4MuxerBuilder {
+        self.sink_fn(move |chunk| {
+            sink.call1(&JsValue::NULL, &js_sys::Uint8Array::from(chunk))
+                .map(|_| ())
+                .map_err(|err| MuxError::Sink(format!("{:?}", err)))
+        })
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl Mp4Muxer {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    // `timescale` defaults to microseconds and `default_frame_duration` to 1/60 s.
+    // Pass 0 for `width` or `height` to take the size from the SPS, and a
+    // `max_keyframe_interval` in frames to be warned about sparse keyframes.
+    pub fn new(
+        width: u32,
+        height: u32,
+        description: &[u8],
+        timescale: Option<u32>,
+        default_frame_duration: Option<u32>,
+        max_keyframe_interval: Option<u32>,
+    ) -> Result<Mp4Muxer, MuxError> {
+        log::info("Mp4Muxer::new called with config");
+
+        let mut builder = Mp4MuxerBuilder::new()
+            .width(width)
+            .height(height)
+            .avcc(description)
+            .timescale(timescale.unwrap_or(DEFAULT_TIMESCALE))
+            .max_keyframe_interval(max_keyframe_interval.unwrap_or(0));
+        if let Some(duration) = default_frame_duration {
+            builder = builder.default_frame_duration(duration);
+        }
+        builder.build()
+    }
+
+    // Same as `new` for an `hvc1` stream described by an HEVCDecoderConfigurationRecord
+    pub fn new_hevc(width: u32, height: u32, description: &[u8]) -> Result<Mp4Muxer, MuxError> {
+        let muxer = Mp4MuxerBuilder::new()
+            .width(width)
+            .height(height)
+            .hvcc(description)
+            .build()?;
+        Ok(muxer)
+    }
+
+    fn inner(&mut self) -> &mut InnerMuxer {
+        unsafe { &mut *(self.inner as *mut InnerMuxer) }
+    }
+
+    // Clockwise rotation (0, 90, 180 or 270) for players to apply via the track
+    // matrix. Must be set before the first frame.
+    pub fn set_rotation(&mut self, degrees: u16) -> Result<(), MuxError> {
+        let inner = self.inner();
+        // Refused here rather than failing `finish` at the end of a recording
+        let (width, height) = inner.dimensions;
+        rotation_matrix(degrees, width, height)?;
+        inner.check_not_started("rotation")?;
+        inner.extras.rotation = degrees;
+        // The init segment was already built; rewrite its matrix directly
+        if let MuxOutput::Fragmented(ref mut fragments) = inner.output {
+            write_rotation(&mut fragments.init_segment, degrees)?;
+        }
+        Ok(())
+    }
+
+    // Colour description of the video (ITU-T H.273 code points, e.g. 12, 13, 1
+    // for Display P3 or 9, 16, 9 for BT.2100 PQ), written as an nclx colr box.
+    // Without it players assume BT.709. Must be set before the first frame.
+    pub fn set_color_info(&mut self, primaries: u16, transfer: u16, matrix: u16, full_range: bool) -> Result<(), MuxError> {
+        let inner = self.inner();
+        inner.check_not_started("color info")?;
+        inner.extras.color = Some(ColorInfo { primaries, transfer, matrix, full_range });
+        if let MuxOutput::Fragmented(ref mut fragments) = inner.output {
+            write_color(&mut fragments.init_segment, &inner.extras)?;
+        }
+        Ok(())
+    }
+
+    // HDR mastering display metadata (SMPTE ST 2086), as found in the encoder's
+    // SEI: `primaries` is x, y for green, blue and red in 0.00002 units, and
+    // luminances are in 0.0001 cd/m^2. Must be set before the first frame.
+    pub fn set_mastering_display(
+        &mut self,
+        primaries: &[u16],
+        white_x: u16,
+        white_y: u16,
+        max_luminance: u32,
+        min_luminance: u32,
+    ) -> Result<(), MuxError> {
+        let &[gx, gy, bx, by, rx, ry] = primaries else {
+            return Err(MuxError::InvalidConfig(format!(
+                "mastering display needs 6 primary coordinates, got {}", primaries.len()
+            )));
+        };
+        let inner = self.inner();
+        inner.check_not_started("mastering display")?;
+        inner.extras.mastering_display = Some(MasteringDisplay {
+            primaries: [(gx, gy), (bx, by), (rx, ry)],
+            white_point: (white_x, white_y),
+            max_luminance,
+            min_luminance,
+        });
+        if let MuxOutput::Fragmented(ref mut fragments) = inner.output {
+            write_color(&mut fragments.init_segment, &inner.extras)?;
+        }
+        Ok(())
+    }
+
+    // JPEG or PNG thumbnail, written as cover art (`covr`) when a progressive
+    // file is finished and handed back by `get_poster`
+    pub fn set_poster(&mut self, image: &[u8]) -> Result<(), MuxError> {
+        if poster_data_type(image).is_none() {
+            return Err(MuxError::InvalidConfig("poster must be a JPEG or PNG image".into()));
+        }
+        self.inner().extras.metadata.poster = Some(image.to_vec());
+        Ok(())
+    }
+
+    // The image given to `set_poster`; empty if there is none
+    pub fn get_poster(&self) -> Vec<u8> {
+        self.inner_ref().extras.metadata.poster.clone().unwrap_or_default()
+    }
+
+    // The first video keyframe exactly as it was added (AVCC, or the converted
+    // Annex B), for a thumbnail from a single VideoDecoder call instead of
+    // demuxing the file. Empty until a keyframe has been added.
+    pub fn get_first_keyframe(&self) -> Vec<u8> {
+        self.inner_ref().first_keyframe.as_deref().map(<[u8]>::to_vec).unwrap_or_default()
+    }
+
+    // For encoders that switch parameter sets mid-stream (e.g. a resolution
+    // change), with the new decoder config description in the same format the
+    // muxer was built with. The sets are repeated in front of every keyframe
+    // from then on and the sample entry becomes avc3/hev1, which allows them
+    // to change.
+    pub fn update_parameter_sets(&mut self, description: &[u8]) -> Result<(), MuxError> {
+        let inner = self.inner();
+        let sets = match inner.extras.hvcc {
+            Some(_) => parse_hvcc(description)?,
+            None => parse_avcc(description)?,
+        };
+        if sets == inner.active_sets {
+            return Ok(());
+        }
+
+        let mut prefix = Vec::new();
+        for nalu in sets.vps.iter().chain(&sets.sps).chain(&sets.pps) {
+            prefix.extend_from_slice(&(nalu.len() as u32).to_be_bytes());
+            prefix.extend_from_slice(nalu);
+        }
+        inner.inband_sets = Some(prefix);
+        inner.active_sets = sets;
+
+        if !inner.extras.inband_parameter_sets {
+            inner.extras.inband_parameter_sets = true;
+            if let MuxOutput::Fragmented(ref mut fragments) = inner.output {
+                allow_inband_parameter_sets(&mut fragments.init_segment)?;
+                log::warn("Parameter sets changed after the init segment; fetch it again for new consumers");
+            }
+        }
+        Ok(())
+    }
+
+    // Have players skip the first `micros` of the recording (e.g. encoder
+    // warm-up) through an edit list, without dropping any samples. Trims longer
+    // than a track are clamped to its length. Progressive output only.
+    pub fn set_start_trim(&mut self, micros: u64) -> Result<(), MuxError> {
+        let inner = self.inner();
+        if let MuxOutput::Fragmented(_) = inner.output {
+            return Err(MuxError::InvalidConfig("start trim needs progressive output".into()));
+        }
+        let trim = micros as u128 * inner.timescale as u128 / 1_000_000;
+        inner.extras.start_trim = trim.min(u64::MAX as u128) as u64;
+        Ok(())
+    }
+
+    // Starts a chapter called `title` at `timestamp`, in the units of the frame
+    // timestamps (and with paused time cut out the same way). `finish` writes
+    // the chapters as a text track the video links to, which QuickTime and
+    // Safari list as chapters. Progressive output only.
+    pub fn add_chapter(&mut self, title: &str, timestamp: u64) -> Result<(), MuxError> {
+        let inner = self.inner();
+        if let MuxOutput::Fragmented(_) = inner.output {
+            return Err(MuxError::InvalidConfig("chapters need progressive output".into()));
+        }
+        if title.len() > u16::MAX as usize {
+            return Err(MuxError::InvalidConfig(format!(
+                "chapter title must be at most {} bytes, got {}", u16::MAX, title.len()
+            )));
+        }
+        let timestamp = timestamp.saturating_sub(inner.pause.offset);
+        inner.chapters.push((timestamp, title.to_owned()));
+        Ok(())
+    }
+
+    // Marks the start of a paused span, when frames stop arriving while the
+    // capture clock keeps running. Frames still flushed out before `resume`
+    // are written as usual.
+    pub fn pause(&mut self) {
+        let inner = self.inner();
+        if !inner.pause.paused {
+            inner.pause.paused = true;
+            log::info("Mp4Muxer paused");
+        }
+    }
+
+    // Ends a pause: the first sample after it starts one frame after the last
+    // one before it, and all later timestamps move back by the paused time,
+    // so the output plays straight through with the pause removed
+    pub fn resume(&mut self) {
+        let pause = &mut self.inner().pause;
+        if pause.paused {
+            pause.paused = false;
+            pause.resuming = true;
+            log::info("Mp4Muxer resumed");
+        }
+    }
+
+    pub fn add_frame(&mut self, data: &[u8], is_key: bool, timestamp: u64) -> Result<(), MuxError> {
+        self.inner().write_sample(VIDEO_TRACK_ID, data, is_key, timestamp, 0)?;
+        Ok(())
+    }
+
+    // `add_frame` for any track by id, e.g. one added with the builder's `video_track`
+    pub fn add_frame_track(&mut self, track_id: u32, data: &[u8], is_key: bool, timestamp: u64) -> Result<(), MuxError> {
+        self.inner().write_sample(track_id, data, is_key, timestamp, 0)?;
+        Ok(())
+    }
+
+    // Same as `add_frame` for encoders that emit Annex B (start-code delimited)
+    // NAL units instead of AVCC length-prefixed ones
+    pub fn add_frame_annexb(&mut self, data: &[u8], is_key: bool, timestamp: u64) -> Result<(), MuxError> {
+        self.inner().write_sample(VIDEO_TRACK_ID, &nal::annexb_to_avcc(data), is_key, timestamp, 0)?;
+        Ok(())
+    }
+
+    // For streams with B-frames, where decode order differs from presentation
+    // order. Frames must arrive in decode order, as WebCodecs emits them.
+    pub fn add_frame_reordered(&mut self, data: &[u8], is_key: bool, dts: u64, pts: u64) -> Result<(), MuxError> {
+        let offset = i32::try_from(pts as i128 - dts as i128).map_err(|_| {
+            MuxError::InvalidConfig(format!("composition offset {} - {} is out of range", pts, dts))
+        })?;
+        self.inner().write_sample(VIDEO_TRACK_ID, data, is_key, dts, offset)?;
+        Ok(())
+    }
+
+    // `add_frame_reordered` with the bookkeeping done here: frames must come in
+    // decode order (strictly increasing `dts`) with distinct `pts`, and may be
+    // shown up to a second before they decode. Progressive files get
+    // non-negative composition offsets, the whole video being shifted by the
+    // largest lead and its edit list starting that much later to compensate.
+    pub fn add_frame_ordered(&mut self, data: &[u8], is_key: bool, dts: u64, pts: u64) -> Result<(), MuxError> {
+        let inner = self.inner();
+        let max_lead = inner.timescale as u64 * MAX_PRESENTATION_LEAD_SECS;
+        let offset = inner.ordered.offset(dts, pts, max_lead)?;
+        // A skipped frame must not be what later frames are checked against
+        if inner.write_sample(VIDEO_TRACK_ID, data, is_key, dts, offset)? {
+            inner.ordered.accept(dts, pts);
+            inner.extras.shift_composition = true;
+        }
+        Ok(())
+    }
+
+    // Duration (timescale units) of the last video frame, which otherwise gets
+    // the median frame spacing since nothing follows it to measure against
+    pub fn set_last_frame_duration(&mut self, duration: u32) -> Result<(), MuxError> {
+        if duration == 0 {
+            return Err(MuxError::InvalidConfig("last frame duration must be non-zero".into()));
+        }
+        self.inner().video.last_sample_duration = Some(duration);
+        Ok(())
+    }
+
+    // Every AAC frame is a sync sample
+    pub fn add_audio_frame(&mut self, data: &[u8], timestamp: u64) -> Result<(), MuxError> {
+        let inner = self.inner();
+        let track_id = inner.audio_track_id()?;
+        inner.write_sample(track_id, data, true, timestamp, 0)?;
+        Ok(())
+    }
+
+    // ftyp+moov to hand to MediaSource before any fragment (fragmented mode only)
+    pub fn init_segment(&mut self) -> Result<Vec<u8>, MuxError> {
+        match self.inner().output {
+            MuxOutput::Fragmented(ref fragments) => Ok(fragments.init_segment.clone()),
+            MuxOutput::Progressive(_) => Err(not_fragmented()),
+        }
+    }
+
+    // moof+mdat for every sample added since the last call; empty if there are none
+    pub fn take_fragment(&mut self) -> Result<Vec<u8>, MuxError> {
+        let inner = self.inner();
+        let fragment = match inner.output {
+            MuxOutput::Fragmented(ref mut fragments) => fragments.take_fragment(),
+            MuxOutput::Progressive(_) => return Err(not_fragmented()),
+        };
+        // With a sink, an early cut goes the same way as the automatic ones
+        if inner.sink.is_some() {
+            inner.emit(&fragment)?;
+            return Ok(Vec::new());
+        }
+        Ok(fragment)
+    }
+
+    // Bytes of the progressive file finished since the last drain, so long
+    // recordings can be uploaded (and freed) as they go. `finish` then returns
+    // only the remainder; all drains plus that tail form the complete file.
+    pub fn drain_buffer(&mut self) -> Result<Vec<u8>, MuxError> {
+        match self.inner().output {
+            MuxOutput::Progressive(ref writer) if writer.faststart => Err(MuxError::InvalidConfig(
+                "faststart output is only available from finish".into()
+            )),
+            MuxOutput::Progressive(ref mut writer) => Ok(writer.drain()),
+            MuxOutput::Fragmented(_) => Err(MuxError::InvalidConfig(
+                "fragmented output is drained with take_fragment".into()
+            )),
+        }
+    }
+
+    // The replay buffer's current contents as a standalone mp4, with
+    // timestamps starting at zero. Recording carries on afterwards.
+    pub fn export_clip(&mut self) -> Result<Vec<u8>, MuxError> {
+        let inner = self.inner();
+        match inner.replay.as_ref() {
+            Some(replay) => replay.export(&inner.extras, inner.video.last_sample_duration, &inner.chapters, &mut |_| {}),
+            None => Err(MuxError::InvalidConfig("muxer was not built with a replay buffer".into())),
+        }
+    }
+
+    // Progressive mode returns the complete file (minus anything drained); fragmented mode returns the
+    // final fragment (the init segment and earlier fragments were already taken).
+    // Fails with `NoFrames` if no video frame was ever added, in every mode.
+    pub fn finish(self) -> Result<Vec<u8>, MuxError> {
+        self.into_inner().finish(&mut |_| {})
+    }
+
+    // Cancel the recording: frees the buffered samples and writer without
+    // finalizing or building any output. Dropping the muxer (`free()` in JS)
+    // releases the same memory; this just makes the cancel path explicit.
+    pub fn abort(self) {
+        log::info("Mp4Muxer aborted");
+        drop(self);
+    }
+}
+
+// Progress callbacks take closures, which wasm-bindgen can't export
+impl Mp4Muxer {
+    // `finish` for long recordings, calling `progress` with a rising fraction:
+    // 0.0, 0.1 once the mp4 crate has written its moov, then every 1% or so
+    // while the sample tables are patched track by track, and 1.0 at the end.
+    // The crate's own step before 0.1 can't be split. Progress is reported,
+    // not yielded: to keep a page responsive, mux in a web worker (nothing
+    // here needs the DOM).
+    pub fn finish_with_progress_fn(self, mut progress: impl FnMut(f64)) -> Result<Vec<u8>, MuxError> {
+        self.into_inner().finish(&mut progress)
+    }
+
+    fn into_inner(mut self) -> InnerMuxer {
+        unsafe {
+            let inner_box = Box::from_raw(self.inner as *mut InnerMuxer);
+            // Ownership moved out; stop Drop from freeing it a second time
+            self.inner = std::ptr::null_mut();
+            *inner_box // take ownership
+        }
+    }
+}
+
+// Plain-Rust accessors behind the JS getters
+impl Mp4Muxer {
+    fn inner_ref(&self) -> &InnerMuxer {
+        unsafe { &*(self.inner as *const InnerMuxer) }
+    }
+
+    pub fn stats(&self) -> MuxStats {
+        let inner = self.inner_ref();
+        let duration = inner.video.total_duration() as f64 / inner.timescale as f64;
+        let bytes = inner.tracks().map(|track| track.bytes).sum();
+        let skipped = inner.tracks().map(|track| track.skipped).sum();
+        MuxStats {
+            duration,
+            frame_count: inner.video.sample_count,
+            extra_frame_count: inner.extra_video.iter().map(|track| track.sample_count).sum(),
+            bytes,
+            bitrate: if duration > 0.0 { bytes as f64 * 8.0 / duration } else { 0.0 },
+            skipped,
+            max_keyframe_interval: inner.gop.max_interval(),
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+impl Mp4Muxer {
+    // `{ duration, frame_count, extra_frame_count, bytes, bitrate, skipped,
+    // max_keyframe_interval }` for the recording so far
+    #[wasm_bindgen(js_name = stats)]
+    pub fn get_stats(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.stats()).unwrap()
+    }
+
+    // `finish_with_progress_fn` for JS; `callback(fraction)` throwing doesn't stop the finish
+    pub fn finish_with_progress(self, callback: js_sys::Function) -> Result<Vec<u8>, MuxError> {
+        self.finish_with_progress_fn(|fraction| {
+            let _ = callback.call1(&JsValue::NULL, &fraction.into());
+        })
+    }
+}
+
+// The dimensions to write for a video track. Zero for either one means "take
+// both from the SPS"; explicit ones are kept, with a warning if the SPS (only
+// parsed for AVC) codes a different size, since players trust the track header.
+fn resolve_dimensions(width: u32, height: u32, codec: VideoCodec, sets: &ParameterSets) -> Result<(u32, u32), MuxError> {
+    let coded = match codec {
+        VideoCodec::Avc => sets.sps.first().and_then(|sps| avc_sps_dimensions(sps)),
+        VideoCodec::Hevc => None,
+    };
+    let (width, height) = match coded {
+        Some((coded_width, coded_height)) if width == 0 || height == 0 => {
+            log::info(&format!("Using the SPS dimensions {}x{}", coded_width, coded_height));
+            (coded_width, coded_height)
+        }
+        Some((coded_width, coded_height)) if (coded_width, coded_height) != (width, height) => {
+            log::warn(&format!(
+                "Configured {}x{} but the SPS codes {}x{}; the video may display stretched",
+                width, height, coded_width, coded_height
+            ));
+            (width, height)
+        }
+        _ => (width, height),
+    };
+    check_dimensions(width, height)?;
+    Ok((width, height))
+}
+
+fn check_dimensions(width: u32, height: u32) -> Result<(), MuxError> {
+    if width == 0 || height == 0 {
+        return Err(MuxError::InvalidConfig(format!(
+            "dimensions must be non-zero, got {}x{}", width, height
+        )));
+    }
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(MuxError::InvalidConfig(format!(
+            "dimensions must fit in 16 bits, got {}x{}", width, height
+        )));
+    }
+    Ok(())
+}
+
+fn not_fragmented() -> MuxError {
+    MuxError::InvalidConfig("muxer was not built with fragmented output".into())
+}
+
+impl Drop for Mp4Muxer {
+    fn drop(&mut self) {
+        // Reclaim the writer of a recording that was abandoned without `finish`
+        if !self.inner.is_null() {
+            unsafe {
+                drop(Box::from_raw(self.inner as *mut InnerMuxer));
+            }
+            self.inner = std::ptr::null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SPS + PPS for a tiny High-profile stream; the payloads are never decoded
+    const AVCC: [u8; 17] = [1, 0x64, 0, 0x1f, 0xff, 0xe1, 0, 4, 0x67, 0x64, 0, 0x1f, 1, 0, 2, 0x68, 0xee];
+    const FRAME: u64 = 33_333;
+
+    fn muxer() -> Mp4Muxer {
+        Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC).build().unwrap()
+    }
+
+    fn frame(i: u64) -> [u8; 6] {
+        [0, 0, 0, 2, 0x65, i as u8]
+    }
+
+    fn read(file: Vec<u8>) -> mp4::Mp4Reader<Cursor<Vec<u8>>> {
+        let size = file.len() as u64;
+        mp4::Mp4Reader::read_header(Cursor::new(file), size).unwrap()
+    }
+
+    fn sample_sizes(reader: &mut mp4::Mp4Reader<Cursor<Vec<u8>>>, track_id: u32) -> Vec<usize> {
+        let count = reader.sample_count(track_id).unwrap();
+        (1..=count).map(|id| reader.read_sample(track_id, id).unwrap().unwrap().bytes.len()).collect()
+    }
+
+    fn top_level_boxes(file: &[u8]) -> Vec<String> {
+        let mut boxes = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= file.len() {
+            let size = u32::from_be_bytes(file[offset..offset + 4].try_into().unwrap()) as usize;
+            boxes.push(String::from_utf8_lossy(&file[offset + 4..offset + 8]).into_owned());
+            offset += size.max(8);
+        }
+        boxes
+    }
+
+    fn samples(reader: &mut mp4::Mp4Reader<Cursor<Vec<u8>>>, track_id: u32) -> Vec<(u64, Vec<u8>)> {
+        let count = reader.sample_count(track_id).unwrap();
+        (1..=count)
+            .map(|id| {
+                let sample = reader.read_sample(track_id, id).unwrap().unwrap();
+                (sample.start_time, sample.bytes.to_vec())
+            })
+            .collect()
+    }
+
+    // Large frames, so the writer has finished media to hand out mid-recording
+    fn record(muxer: &mut Mp4Muxer, mut each: impl FnMut(&mut Mp4Muxer, u64)) {
+        for i in 0..300 {
+            let mut data = vec![0; 20_000 + (i as usize % 7) * 1000];
+            data[..6].copy_from_slice(&frame(i));
+            muxer.add_frame(&data, i % 30 == 0, i * FRAME).unwrap();
+            if i % 2 == 0 {
+                muxer.add_audio_frame(&[0x21, i as u8, 3], i * 21_333).unwrap();
+            }
+            each(muxer, i);
+        }
+    }
+
+    fn with_audio() -> Mp4MuxerBuilder {
+        Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC).audio(48000, 2, &[0x11, 0x90])
+    }
+
+    #[test]
+    fn draining_does_not_change_the_file() {
+        let mut whole = with_audio().build().unwrap();
+        record(&mut whole, |_, _| {});
+        let whole = whole.finish().unwrap();
+
+        let mut drained = Vec::new();
+        let mut muxer = with_audio().build().unwrap();
+        record(&mut muxer, |muxer, i| {
+            if i % 37 == 0 {
+                drained.extend(muxer.drain_buffer().unwrap());
+            }
+        });
+        assert!(!drained.is_empty());
+        drained.extend(muxer.finish().unwrap());
+        assert!(drained == whole);
+    }
+
+    #[test]
+    fn faststart_moves_the_moov_ahead_of_identical_media() {
+        let mut plain = with_audio().build().unwrap();
+        record(&mut plain, |_, _| {});
+        let plain = plain.finish().unwrap();
+        let mut fast = with_audio().faststart(true).build().unwrap();
+        record(&mut fast, |_, _| {});
+        assert!(fast.drain_buffer().is_err());
+        let fast = fast.finish().unwrap();
+
+        let (plain_boxes, fast_boxes) = (top_level_boxes(&plain), top_level_boxes(&fast));
+        assert_eq!(plain_boxes.last().map(String::as_str), Some("moov"));
+        assert_eq!(fast_boxes[..2], ["ftyp", "moov"]);
+        assert!(fast_boxes[2..].iter().all(|name| name == "mdat"));
+        let (mut plain, mut fast) = (read(plain), read(fast));
+        for track_id in [VIDEO_TRACK_ID, 2] {
+            assert!(samples(&mut plain, track_id) == samples(&mut fast, track_id), "track {}", track_id);
+        }
+    }
+
+    #[test]
+    fn chapters_are_linked_from_the_video() {
+        for faststart in [false, true] {
+            let mut muxer = with_audio().faststart(faststart).build().unwrap();
+            muxer.add_chapter("Demo", 100 * FRAME).unwrap();
+            muxer.add_chapter("Intro", 0).unwrap();
+            record(&mut muxer, |_, _| {});
+            let file = muxer.finish().unwrap();
+
+            // The chapters are track 3, after video and audio
+            let chap = find_box(&file, &[b"moov", b"trak", b"tref", b"chap"]).unwrap();
+            assert_eq!((chap.size, &file[chap.start + 8..chap.start + 12]), (12, &[0, 0, 0, 3][..]));
+            let mut reader = read(file);
+            let titles: Vec<(u64, String)> = samples(&mut reader, 3).into_iter().map(|(start, text)| {
+                let len = u16::from_be_bytes([text[0], text[1]]) as usize;
+                (start, String::from_utf8(text[2..2 + len].to_vec()).unwrap())
+            }).collect();
+            assert_eq!(titles, [(0, "Intro".to_owned()), (100 * FRAME, "Demo".to_owned())]);
+            let track = &reader.tracks()[&3];
+            assert_eq!(track.track_type().unwrap(), mp4::TrackType::Subtitle);
+            // Shown in the chapter list, not as subtitles
+            assert_eq!(track.trak.tkhd.flags, 2);
+            assert_eq!(track.duration(), reader.tracks()[&VIDEO_TRACK_ID].duration());
+        }
+
+        // Nothing to link without chapters
+        let mut muxer = muxer();
+        muxer.add_frame(&frame(0), true, 0).unwrap();
+        let file = muxer.finish().unwrap();
+        assert!(find_box(&file, &[b"moov", b"trak", b"tref"]).is_err());
+
+        let mut fragmented = Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC).fragmented(true).build().unwrap();
+        assert!(matches!(fragmented.add_chapter("Intro", 0), Err(MuxError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn pauses_are_cut_from_the_timeline() {
+        let mut muxer = muxer();
+        for i in 0..30 {
+            muxer.add_frame(&frame(i), i == 0, i * FRAME).unwrap();
+        }
+        muxer.pause();
+        muxer.resume();
+        // Ten seconds later on the capture clock
+        for i in 330..360 {
+            muxer.add_frame(&frame(i), i == 330, i * FRAME).unwrap();
+        }
+        let mut reader = read(muxer.finish().unwrap());
+        let times: Vec<u64> = samples(&mut reader, VIDEO_TRACK_ID).into_iter().map(|(time, _)| time).collect();
+        assert_eq!(times, (0..60).map(|i| i * FRAME).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn a_failed_write_after_resume_keeps_the_pause_cut() {
+        use std::{cell::{Cell, RefCell}, rc::Rc};
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let fail = Rc::new(Cell::new(false));
+        let (sink_out, sink_fail) = (out.clone(), fail.clone());
+        let mut muxer = Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC)
+            .fragmented(true)
+            .sink_fn(move |chunk| {
+                if sink_fail.get() {
+                    return Err(MuxError::Sink("offline".into()));
+                }
+                sink_out.borrow_mut().extend_from_slice(chunk);
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        for i in 0..3 {
+            muxer.add_frame(&frame(i), i != 1, i * FRAME).unwrap();
+        }
+        muxer.pause();
+        muxer.resume();
+        // Releasing the keyframe at 2 cuts a fragment, which the sink refuses
+        fail.set(true);
+        let late = 300 * FRAME;
+        assert!(matches!(muxer.add_frame(&frame(3), false, late), Err(MuxError::Sink(_))));
+        fail.set(false);
+        muxer.add_frame(&frame(3), false, late).unwrap();
+        assert_eq!(muxer.stats().duration, 4.0 * FRAME as f64 / 1e6);
+        muxer.finish().unwrap();
+
+        let file = out.borrow().clone();
+        assert_eq!(top_level_boxes(&file), ["ftyp", "moov", "moof", "mdat", "moof", "mdat"]);
+        // Each trun here: header, flags, count and data offset, then duration,
+        // size and flags per sample
+        let mut durations = Vec::new();
+        for at in (0..file.len() - 4).filter(|&at| &file[at..at + 4] == b"trun") {
+            let count = u32::from_be_bytes(file[at + 8..at + 12].try_into().unwrap()) as usize;
+            for sample in 0..count {
+                let field = at + 16 + sample * 12;
+                durations.push(u32::from_be_bytes(file[field..field + 4].try_into().unwrap()));
+            }
+        }
+        assert_eq!(durations, [FRAME as u32; 4]);
+    }
+
+    #[test]
+    fn updated_parameter_sets_precede_every_later_keyframe() {
+        let mut avcc = AVCC.to_vec();
+        *avcc.last_mut().unwrap() ^= 1;
+        let mut muxer = muxer();
+        for i in 0..90 {
+            if i == 10 {
+                muxer.update_parameter_sets(&avcc).unwrap();
+            }
+            muxer.add_frame(&frame(i), i % 30 == 0, i * FRAME).unwrap();
+        }
+        let sizes = sample_sizes(&mut read(muxer.finish().unwrap()), VIDEO_TRACK_ID);
+        // 4-byte length prefix + 4-byte SPS, 4 + 2-byte PPS
+        let with_sets = frame(0).len() + 14;
+        assert_eq!([sizes[0], sizes[30], sizes[60]], [frame(0).len(), with_sets, with_sets]);
+        assert_eq!(sizes[31], frame(0).len());
+    }
+
+    #[test]
+    fn finish_reports_progress_through_the_tables() {
+        for faststart in [false, true] {
+            let build = || Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC)
+                .audio(48000, 2, &[0x11, 0x90])
+                .faststart(faststart)
+                .build()
+                .unwrap();
+            let (mut muxer, mut reference) = (build(), build());
+            for i in 0..20_000 {
+                for muxer in [&mut muxer, &mut reference] {
+                    muxer.add_frame(&frame(i), i % 60 == 0, i * FRAME).unwrap();
+                    muxer.add_audio_frame(&[0x21, 0, 0], i * 21_333).unwrap();
+                }
+            }
+            let mut calls = Vec::new();
+            let file = muxer.finish_with_progress_fn(|fraction| calls.push(fraction)).unwrap();
+            assert!(file == reference.finish().unwrap());
+
+            assert!(calls.len() > 50, "{} callbacks", calls.len());
+            assert_eq!((calls[0], calls[calls.len() - 1]), (0.0, 1.0));
+            assert!(calls.windows(2).all(|pair| pair[1] > pair[0]), "{:?}", calls);
+            // Between the crate's moov and the end, no step is much over 1%
+            let tables: Vec<f64> = calls.iter().copied().filter(|f| (WRITE_END_PROGRESS..1.0).contains(f)).collect();
+            assert!(tables.windows(2).all(|pair| pair[1] - pair[0] < 2.0 * PROGRESS_STEP), "{:?}", tables);
+        }
+    }
+
+    #[test]
+    fn finishing_without_frames_fails() {
+        let builders = [
+            Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC),
+            Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC).fragmented(true),
+            Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC).faststart(true),
+            Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC).replay_buffer(2.0),
+        ];
+        for builder in builders {
+            let result = builder.build().unwrap().finish();
+            assert!(matches!(result, Err(MuxError::NoFrames)), "{:?}", result.map(|file| file.len()));
+        }
+        let mut replay = Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC).replay_buffer(2.0).build().unwrap();
+        assert!(matches!(replay.export_clip(), Err(MuxError::NoFrames)));
+    }
+
+    #[test]
+    fn replay_clips_keep_the_poster() {
+        let poster = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 1, 2, 3, 4];
+        let mut muxer = Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC)
+            .replay_buffer(2.0)
+            .build()
+            .unwrap();
+        muxer.set_poster(&poster).unwrap();
+        for i in 0..90 {
+            muxer.add_frame(&frame(i), i % 30 == 0, i * FRAME).unwrap();
+        }
+        let clip = muxer.export_clip().unwrap();
+        assert!(clip.windows(poster.len()).any(|window| window == poster));
+    }
+
+    #[test]
+    fn replay_clips_open_with_the_running_chapter() {
+        let mut muxer = Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC)
+            .replay_buffer(2.0)
+            .build()
+            .unwrap();
+        for (title, i) in [("Setup", 0), ("Build", 60), ("Ship", 130)] {
+            muxer.add_chapter(title, i * FRAME).unwrap();
+        }
+        for i in 0..150 {
+            muxer.add_frame(&frame(i), i % 30 == 0, i * FRAME).unwrap();
+        }
+        // The clip starts on the keyframe at frame 60
+        let mut reader = read(muxer.export_clip().unwrap());
+        let starts: Vec<u64> = samples(&mut reader, 2).into_iter().map(|(start, _)| start).collect();
+        assert_eq!(starts, [0, 70 * FRAME]);
+        assert_eq!(reader.tracks()[&VIDEO_TRACK_ID].sample_count(), 90);
+    }
+
+    #[test]
+    fn rotation_refuses_sizes_the_matrix_cannot_hold() {
+        let mut wide = Mp4MuxerBuilder::new().width(40_000).height(64).avcc(&AVCC).build().unwrap();
+        assert!(wide.set_rotation(90).is_ok());
+        assert!(matches!(wide.set_rotation(270), Err(MuxError::InvalidConfig(_))));
+        assert!(matches!(wide.set_rotation(180), Err(MuxError::InvalidConfig(_))));
+        assert!(matches!(wide.set_rotation(45), Err(MuxError::InvalidConfig(_))));
+
+        let mut muxer = Mp4MuxerBuilder::new().width(32_767).height(64).avcc(&AVCC).build().unwrap();
+        muxer.set_rotation(270).unwrap();
+        muxer.add_frame(&frame(0), true, 0).unwrap();
+        let reader = read(muxer.finish().unwrap());
+        let matrix = &reader.tracks()[&VIDEO_TRACK_ID].trak.tkhd.matrix;
+        assert_eq!(matrix.y, 32_767 << 16);
+    }
+
+    #[test]
+    fn builder_sets_rotation_and_color() {
+        let builder = || Mp4MuxerBuilder::new().width(64).height(48).avcc(&AVCC).rotation(90).color(12, 13, 1, true);
+        // nclx: primaries, transfer and matrix code points, then the full-range flag
+        let colr = [b"colrnclx".as_slice(), &[0, 12, 0, 13, 0, 1, 0x80]].concat();
+        let has_colr = |file: &[u8]| file.windows(colr.len()).any(|w| w == colr.as_slice());
+
+        let mut muxer = builder().build().unwrap();
+        muxer.add_frame(&frame(0), true, 0).unwrap();
+        let file = muxer.finish().unwrap();
+        assert!(has_colr(&file));
+        let reader = read(file);
+        let tkhd = &reader.tracks()[&VIDEO_TRACK_ID].trak.tkhd;
+        assert_eq!((tkhd.matrix.b, tkhd.matrix.x), (1 << 16, 48 << 16));
+
+        let mut fragmented = builder().fragmented(true).build().unwrap();
+        let init = fragmented.init_segment().unwrap();
+        assert!(has_colr(&init));
+        assert_eq!(read(init).tracks()[&VIDEO_TRACK_ID].trak.tkhd.matrix.b, 1 << 16);
+
+        let bad = Mp4MuxerBuilder::new().width(64).height(48).avcc(&AVCC).rotation(45).build();
+        assert!(matches!(bad, Err(MuxError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn ordered_frames_can_be_retried_after_a_failed_write() {
+        let mut muxer = muxer();
+        // A delta frame first is refused, and must not use up its timestamps
+        assert!(matches!(muxer.add_frame_ordered(&frame(0), false, 0, FRAME), Err(MuxError::MissingKeyframe)));
+        muxer.add_frame_ordered(&frame(0), true, 0, FRAME).unwrap();
+        muxer.add_frame_ordered(&frame(1), false, FRAME, 3 * FRAME).unwrap();
+        muxer.add_frame_ordered(&frame(2), false, 2 * FRAME, 2 * FRAME).unwrap();
+        assert!(muxer.add_frame_ordered(&frame(3), false, 2 * FRAME, 4 * FRAME).is_err());
+        let reader = read(muxer.finish().unwrap());
+        assert_eq!(reader.sample_count(VIDEO_TRACK_ID).unwrap(), 3);
+    }
+
+    #[test]
+    fn skipped_ordered_frames_leave_their_timestamps_free() {
+        let mut muxer = muxer();
+        muxer.add_frame_ordered(&frame(0), true, 0, FRAME).unwrap();
+        // Dropped as empty, so its dts and pts can still be used
+        muxer.add_frame_ordered(&[], false, FRAME, 3 * FRAME).unwrap();
+        muxer.add_frame_ordered(&frame(1), false, FRAME, 3 * FRAME).unwrap();
+        muxer.add_frame_ordered(&frame(2), false, 2 * FRAME, 2 * FRAME).unwrap();
+        // A repeated dts is still refused once a frame was written with it
+        assert!(muxer.add_frame_ordered(&frame(3), false, 2 * FRAME, 4 * FRAME).is_err());
+        let mut reader = read(muxer.finish().unwrap());
+        assert_eq!(sample_sizes(&mut reader, VIDEO_TRACK_ID), vec![6, 6, 6]);
+    }
+
+    #[test]
+    fn stats_count_every_track() {
+        let mut muxer = Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC)
+            .audio(48000, 2, &[0x11, 0x90])
+            .video_track(32, 32, &AVCC)
+            .build()
+            .unwrap();
+        for i in 0..10 {
+            muxer.add_frame(&frame(i), i == 0, i * FRAME).unwrap();
+            muxer.add_frame_track(3, &[0, 0, 0, 5, 0x65, 1, 2, 3, 4], i == 0, i * FRAME).unwrap();
+            muxer.add_audio_frame(&[0x21, 0, 0], i * 21_333).unwrap();
+        }
+        let stats = muxer.stats();
+        assert_eq!((stats.frame_count, stats.extra_frame_count), (10, 10));
+        assert_eq!(stats.bytes, 10 * (6 + 9 + 3));
+    }
+
+    #[test]
+    fn capture_gaps_keep_audio_and_video_in_sync() {
+        let mut muxer = Mp4MuxerBuilder::new().width(64).height(64).avcc(&AVCC)
+            .audio(48000, 2, &[0x11, 0x90])
+            .build()
+            .unwrap();
+        // One second of media, a four second stall on both tracks, one more second
+        const GAP: u64 = 4_000_000;
+        for i in 0..60 {
+            let shift = if i < 30 { 0 } else { GAP };
+            muxer.add_frame(&frame(i), i % 30 == 0, i * FRAME + shift).unwrap();
+        }
+        for i in 0..94 {
+            let shift = if i < 47 { 0 } else { GAP };
+            muxer.add_audio_frame(&[0x21, i as u8], i * 21_333 + shift).unwrap();
+        }
+        let reader = read(muxer.finish().unwrap());
+        let video = reader.tracks()[&VIDEO_TRACK_ID].duration().as_secs_f64();
+        let audio = reader.tracks()[&2].duration().as_secs_f64();
+        assert!((video - 6.0).abs() < 0.05, "video {}", video);
+        assert!((video - audio).abs() < 0.05, "video {} audio {}", video, audio);
+    }
+}
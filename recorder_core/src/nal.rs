@@ -0,0 +1,128 @@
+// H.264 bitstream helpers with no wasm dependency
+
+// H.264 nal_unit_type values for parameter sets
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+
+// Splits an Annex B stream on its 3- or 4-byte start codes, yielding NAL unit payloads
+pub fn annexb_nalus(input: &[u8]) -> impl Iterator<Item = &[u8]> {
+    // Offsets just past each 00 00 01
+    let mut starts = Vec::new();
+    let mut i = 0;
+    while i + 3 <= input.len() {
+        if input[i] == 0 && input[i + 1] == 0 && input[i + 2] == 1 {
+            starts.push(i + 3);
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    // Each NAL unit runs up to the next start code, the last one to the end
+    let ends: Vec<usize> = starts
+        .iter()
+        .skip(1)
+        .map(|&next| next - 3)
+        .chain(std::iter::once(input.len()))
+        .collect();
+    starts.into_iter().zip(ends).filter_map(move |(start, end)| {
+        // Drops the leading zero of a 4-byte start code and any trailing_zero_8bits
+        let mut end = end;
+        while end > start && input[end - 1] == 0 {
+            end -= 1;
+        }
+        (end > start).then(|| &input[start..end])
+    })
+}
+
+// Rewrites Annex B NAL units with the 4-byte big-endian length prefixes an
+// mp4 sample needs (matching the length size the avcC declares)
+pub fn annexb_to_avcc(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() + 16);
+    for nalu in annexb_nalus(input) {
+        out.extend_from_slice(&(nalu.len() as u32).to_be_bytes());
+        out.extend_from_slice(nalu);
+    }
+    out
+}
+
+// Splits an AVCC sample (4-byte big-endian length prefixes) into its NAL unit
+// payloads, stopping at the first length that runs past the end
+pub fn avcc_nalus(input: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = input;
+    std::iter::from_fn(move || {
+        let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let nalu = rest.get(4..4 + len)?;
+        rest = &rest[4 + len..];
+        Some(nalu)
+    })
+}
+
+// SPS and PPS NAL units (without start codes) found in an Annex B access unit,
+// e.g. an IDR frame from an encoder that sends parameter sets in-band
+pub fn extract_parameter_sets(annexb: &[u8]) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    let mut sps = Vec::new();
+    let mut pps = Vec::new();
+    for nalu in annexb_nalus(annexb) {
+        match nalu[0] & 0x1F {
+            NAL_SPS => sps.push(nalu.to_vec()),
+            NAL_PPS => pps.push(nalu.to_vec()),
+            _ => {}
+        }
+    }
+    (sps, pps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: [u8; 4] = [0x67, 0x42, 0xC0, 0x1E];
+    const PPS: [u8; 2] = [0x68, 0xCE];
+    const IDR: [u8; 3] = [0x65, 0x88, 0x84];
+
+    // SPS, PPS and IDR slice as an encoder emits a keyframe, with both start code lengths
+    fn keyframe() -> Vec<u8> {
+        [&[0, 0, 0, 1][..], &SPS, &[0, 0, 1], &PPS, &[0, 0, 0, 1], &IDR].concat()
+    }
+
+    #[test]
+    fn converts_both_start_code_lengths() {
+        let avcc = annexb_to_avcc(&keyframe());
+        let expected = [&[0, 0, 0, 4][..], &SPS, &[0, 0, 0, 2], &PPS, &[0, 0, 0, 3], &IDR].concat();
+        assert_eq!(avcc, expected);
+        let nalus: Vec<&[u8]> = avcc_nalus(&avcc).collect();
+        assert_eq!(nalus, [&SPS[..], &PPS, &IDR]);
+    }
+
+    #[test]
+    fn drops_trailing_zero_bytes() {
+        let input = [&[0, 0, 1][..], &IDR, &[0, 0, 0], &[0, 0, 1], &PPS, &[0, 0]].concat();
+        let nalus: Vec<&[u8]> = annexb_nalus(&input).collect();
+        assert_eq!(nalus, [&IDR[..], &PPS]);
+    }
+
+    #[test]
+    fn empty_or_unframed_input_has_no_nal_units() {
+        assert!(annexb_to_avcc(&[]).is_empty());
+        assert!(annexb_to_avcc(&IDR).is_empty());
+        assert!(annexb_to_avcc(&[0, 0, 0, 1]).is_empty());
+        assert_eq!(extract_parameter_sets(&[]), (Vec::new(), Vec::new()));
+        assert_eq!(avcc_nalus(&[]).count(), 0);
+    }
+
+    #[test]
+    fn extracts_parameter_sets_from_a_keyframe() {
+        let (sps, pps) = extract_parameter_sets(&keyframe());
+        assert_eq!((sps, pps), (vec![SPS.to_vec()], vec![PPS.to_vec()]));
+
+        let delta = [&[0, 0, 0, 1][..], &[0x41, 0x9A]].concat();
+        assert_eq!(extract_parameter_sets(&delta), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn avcc_stops_at_a_truncated_length() {
+        let avcc = [&[0, 0, 0, 3][..], &IDR, &[0, 0, 0, 9, 0x65]].concat();
+        assert_eq!(avcc_nalus(&avcc).collect::<Vec<_>>(), [&IDR[..]]);
+    }
+}
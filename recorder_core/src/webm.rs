@@ -0,0 +1,621 @@
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::error::MuxError;
+use crate::log;
+use crate::muxer::TrackState;
+
+// Matroska element ids (already including their length marker bits)
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CODEC_DELAY: u32 = 0x56AA;
+const SEEK_PRE_ROLL: u32 = 0x56BB;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+const VIDEO_TRACK: u64 = 1;
+const AUDIO_TRACK: u64 = 2;
+// Block timestamps are in ms (the default 1,000,000 ns timestamp scale)
+const NS_PER_TICK: u64 = 1_000_000;
+const US_PER_TICK: u64 = NS_PER_TICK / 1000;
+// How far one track may run ahead of the other before its blocks are written
+// without waiting; WebCodecs audio trails video by far less than this
+const MAX_INTERLEAVE_US: u64 = 1_000_000;
+// Opus decoders need 80 ms of audio before a seek point to converge
+const OPUS_SEEK_PRE_ROLL_NS: u64 = 80_000_000;
+// Durations (us) assumed for a last sample with nothing before it to measure:
+// a 60 fps frame and WebCodecs' default 20 ms Opus packet
+const DEFAULT_FRAME_US: u32 = 16_667;
+const DEFAULT_OPUS_PACKET_US: u32 = 20_000;
+
+// Writes an element id, then `len` as a minimal-length EBML size
+fn write_header(out: &mut Vec<u8>, id: u32, len: u64) {
+    let id_bytes = id.to_be_bytes();
+    let skip = id_bytes.iter().take_while(|&&b| b == 0).count();
+    out.extend_from_slice(&id_bytes[skip..]);
+
+    // n bytes hold 7n bits of size; all ones is reserved for "unknown"
+    let n = (1..=8).find(|&n| len < (1u64 << (7 * n)) - 1).unwrap_or(8);
+    let marked = len | (1u64 << (7 * n));
+    out.extend_from_slice(&marked.to_be_bytes()[8 - n as usize..]);
+}
+
+// Appends a master element whose children are produced by `body`
+fn element(out: &mut Vec<u8>, id: u32, body: impl FnOnce(&mut Vec<u8>)) {
+    let mut children = Vec::new();
+    body(&mut children);
+    write_header(out, id, children.len() as u64);
+    out.extend_from_slice(&children);
+}
+
+fn bytes_element(out: &mut Vec<u8>, id: u32, value: &[u8]) {
+    write_header(out, id, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+// Unsigned integers use as few bytes as the value needs
+fn uint_element(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    bytes_element(out, id, &bytes[skip..]);
+}
+
+fn float_element(out: &mut Vec<u8>, id: u32, value: f64) {
+    bytes_element(out, id, &value.to_be_bytes());
+}
+
+// A block waiting for the other track to catch up, so blocks go out in
+// timestamp order
+struct QueuedBlock {
+    track: u64,
+    data: Vec<u8>,
+    is_key: bool,
+    // Microseconds
+    timestamp: u64,
+}
+
+// A cluster still being filled
+struct OpenCluster {
+    // Absolute timestamp in ticks; blocks store an i16 offset from it
+    timestamp: u64,
+    blocks: Vec<u8>,
+}
+
+struct OpusTrack {
+    sample_rate: u32,
+    channel_count: u32,
+    // OpusHead identification header
+    codec_private: Vec<u8>,
+    timing: TrackState,
+}
+
+// WebM (Matroska) output for VP8, VP9 and AV1 video with optional Opus audio,
+// the WebCodecs codecs MP4 players are least happy with. Like the progressive
+// Mp4Muxer it keeps the file in memory and returns it from `finish`; a new
+// cluster (and seek cue) starts at every video keyframe. Blocks are written
+// in timestamp order across both tracks, whichever order they arrive in.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub struct WebmMuxer {
+    width: u32,
+    height: u32,
+    codec_id: &'static str,
+    codec_private: Vec<u8>,
+    audio: Option<OpusTrack>,
+    // Finished clusters, back to back
+    clusters: Vec<u8>,
+    cluster: Option<OpenCluster>,
+    // (timestamp in ticks, offset into `clusters`) of each keyframe cluster
+    cues: Vec<(u64, u64)>,
+    // Accepted blocks not written yet, per track, in arrival order
+    video_queue: std::collections::VecDeque<QueuedBlock>,
+    audio_queue: std::collections::VecDeque<QueuedBlock>,
+    // Sample timing in us, handled exactly as in Mp4Muxer
+    video: TrackState,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl WebmMuxer {
+    // `codec` is a WebCodecs codec string ("vp8", "vp09.00.10.08", "av01.0.04M.08")
+    // or a Matroska codec id ("V_VP9"). `description` is the decoder config
+    // description if the encoder gave one (required for AV1), else empty.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(width: u32, height: u32, codec: &str, description: &[u8]) -> Result<WebmMuxer, MuxError> {
+        if width == 0 || height == 0 {
+            return Err(MuxError::InvalidConfig(format!(
+                "dimensions must be non-zero, got {}x{}", width, height
+            )));
+        }
+        let codec_id = match codec {
+            "V_VP8" | "vp8" => "V_VP8",
+            "V_VP9" => "V_VP9",
+            "V_AV1" => "V_AV1",
+            _ if codec.starts_with("vp09") || codec == "vp9" => "V_VP9",
+            _ if codec.starts_with("av01") => "V_AV1",
+            _ => return Err(MuxError::InvalidConfig(format!("unsupported WebM video codec {:?}", codec))),
+        };
+        if codec_id == "V_AV1" && description.is_empty() {
+            return Err(MuxError::InvalidConfig("AV1 needs its av1C description".into()));
+        }
+        log::info(&format!("WebmMuxer initialized for {}", codec_id));
+
+        Ok(WebmMuxer {
+            width,
+            height,
+            codec_id,
+            codec_private: description.to_vec(),
+            audio: None,
+            clusters: Vec::new(),
+            cluster: None,
+            cues: Vec::new(),
+            video_queue: std::collections::VecDeque::new(),
+            audio_queue: std::collections::VecDeque::new(),
+            video: TrackState::new(VIDEO_TRACK as u32, DEFAULT_FRAME_US),
+        })
+    }
+
+    // Adds an Opus track; `description` is WebCodecs' audio description (the
+    // OpusHead) if there is one. Must be called before the first frame.
+    pub fn add_audio_track(&mut self, sample_rate: u32, channel_count: u32, description: &[u8]) -> Result<(), MuxError> {
+        if self.video.has_samples() || self.cluster.is_some() {
+            return Err(MuxError::InvalidConfig("audio track must be added before the first frame".into()));
+        }
+        if sample_rate == 0 || channel_count == 0 || channel_count > 255 {
+            return Err(MuxError::InvalidConfig(format!(
+                "unsupported Opus config: {} Hz, {} channels", sample_rate, channel_count
+            )));
+        }
+        let codec_private = if description.is_empty() {
+            // Minimal OpusHead: version 1, no pre-skip, mapping family 0
+            let mut head = b"OpusHead".to_vec();
+            head.push(1);
+            head.push(channel_count as u8);
+            head.extend_from_slice(&0u16.to_le_bytes());
+            head.extend_from_slice(&sample_rate.to_le_bytes());
+            head.extend_from_slice(&0i16.to_le_bytes());
+            head.push(0);
+            head
+        } else {
+            description.to_vec()
+        };
+        self.audio = Some(OpusTrack {
+            sample_rate,
+            channel_count,
+            codec_private,
+            timing: TrackState::new(AUDIO_TRACK as u32, DEFAULT_OPUS_PACKET_US),
+        });
+        Ok(())
+    }
+
+    // `timestamp` is in microseconds, as on WebCodecs chunks. Empty frames
+    // and repeated timestamps are dropped, as Mp4Muxer drops them.
+    pub fn add_frame(&mut self, data: &[u8], is_key: bool, timestamp: u64) -> Result<(), MuxError> {
+        if !self.video.has_samples() && !is_key {
+            return Err(MuxError::MissingKeyframe);
+        }
+        if self.video.accept_timing(data, timestamp) {
+            self.queue(QueuedBlock { track: VIDEO_TRACK, data: data.to_vec(), is_key, timestamp });
+        }
+        Ok(())
+    }
+
+    // Every Opus packet is a keyframe
+    pub fn add_audio_frame(&mut self, data: &[u8], timestamp: u64) -> Result<(), MuxError> {
+        let Some(audio) = self.audio.as_mut() else {
+            return Err(MuxError::InvalidConfig("no audio track was configured".into()));
+        };
+        if audio.timing.accept_timing(data, timestamp) {
+            self.queue(QueuedBlock { track: AUDIO_TRACK, data: data.to_vec(), is_key: true, timestamp });
+        }
+        Ok(())
+    }
+
+    // Fails with `NoFrames` if no video frame was added, as Mp4Muxer does
+    pub fn finish(mut self) -> Result<Vec<u8>, MuxError> {
+        if !self.video.has_samples() {
+            return Err(MuxError::NoFrames);
+        }
+        self.write_queued(true);
+        self.close_cluster();
+        // Where the last block of either track ends, not where it starts
+        let audio_end = self.audio.as_ref().map_or(0, |audio| audio.timing.end_timestamp());
+        let duration_ms = self.video.end_timestamp().max(audio_end) as f64 / 1000.0;
+
+        let mut info = Vec::new();
+        element(&mut info, INFO, |info| {
+            uint_element(info, TIMESTAMP_SCALE, NS_PER_TICK);
+            float_element(info, DURATION, duration_ms);
+            bytes_element(info, MUXING_APP, b"recorder_core");
+            bytes_element(info, WRITING_APP, b"recorder_core");
+        });
+
+        let mut tracks = Vec::new();
+        element(&mut tracks, TRACKS, |tracks| {
+            element(tracks, TRACK_ENTRY, |entry| {
+                uint_element(entry, TRACK_NUMBER, VIDEO_TRACK);
+                uint_element(entry, TRACK_UID, VIDEO_TRACK);
+                uint_element(entry, TRACK_TYPE, 1);
+                uint_element(entry, FLAG_LACING, 0);
+                bytes_element(entry, CODEC_ID, self.codec_id.as_bytes());
+                if !self.codec_private.is_empty() {
+                    bytes_element(entry, CODEC_PRIVATE, &self.codec_private);
+                }
+                element(entry, VIDEO, |video| {
+                    uint_element(video, PIXEL_WIDTH, self.width as u64);
+                    uint_element(video, PIXEL_HEIGHT, self.height as u64);
+                });
+            });
+            if let Some(audio) = &self.audio {
+                element(tracks, TRACK_ENTRY, |entry| {
+                    uint_element(entry, TRACK_NUMBER, AUDIO_TRACK);
+                    uint_element(entry, TRACK_UID, AUDIO_TRACK);
+                    uint_element(entry, TRACK_TYPE, 2);
+                    uint_element(entry, FLAG_LACING, 0);
+                    bytes_element(entry, CODEC_ID, b"A_OPUS");
+                    bytes_element(entry, CODEC_PRIVATE, &audio.codec_private);
+                    // Pre-skip (48 kHz samples) from the OpusHead
+                    let pre_skip = audio.codec_private.get(10..12)
+                        .map_or(0, |b| u16::from_le_bytes([b[0], b[1]]) as u64);
+                    uint_element(entry, CODEC_DELAY, pre_skip * 1_000_000_000 / 48_000);
+                    uint_element(entry, SEEK_PRE_ROLL, OPUS_SEEK_PRE_ROLL_NS);
+                    element(entry, AUDIO, |a| {
+                        float_element(a, SAMPLING_FREQUENCY, audio.sample_rate as f64);
+                        uint_element(a, CHANNELS, audio.channel_count as u64);
+                    });
+                });
+            }
+        });
+
+        // SeekHead positions are fixed-width, so its size doesn't depend on them
+        let seek_head = |info_at: u64, tracks_at: u64, cues_at: u64| {
+            let mut out = Vec::new();
+            element(&mut out, SEEK_HEAD, |head| {
+                for (id, at) in [(INFO, info_at), (TRACKS, tracks_at), (CUES, cues_at)] {
+                    element(head, SEEK, |seek| {
+                        bytes_element(seek, SEEK_ID, &id.to_be_bytes());
+                        bytes_element(seek, SEEK_POSITION, &at.to_be_bytes());
+                    });
+                }
+            });
+            out
+        };
+        // Positions are relative to the start of the segment's data
+        let info_at = seek_head(0, 0, 0).len() as u64;
+        let tracks_at = info_at + info.len() as u64;
+        let clusters_at = tracks_at + tracks.len() as u64;
+        let cues_at = clusters_at + self.clusters.len() as u64;
+
+        let mut cues = Vec::new();
+        element(&mut cues, CUES, |cues| {
+            for &(time, offset) in &self.cues {
+                element(cues, CUE_POINT, |point| {
+                    uint_element(point, CUE_TIME, time);
+                    element(point, CUE_TRACK_POSITIONS, |positions| {
+                        uint_element(positions, CUE_TRACK, VIDEO_TRACK);
+                        uint_element(positions, CUE_CLUSTER_POSITION, clusters_at + offset);
+                    });
+                });
+            }
+        });
+
+        let seek_head = seek_head(info_at, tracks_at, cues_at);
+        let segment_len = cues_at + cues.len() as u64;
+
+        let mut out = Vec::with_capacity(segment_len as usize + 64);
+        element(&mut out, EBML, |ebml| {
+            uint_element(ebml, EBML_VERSION, 1);
+            uint_element(ebml, EBML_READ_VERSION, 1);
+            uint_element(ebml, EBML_MAX_ID_LENGTH, 4);
+            uint_element(ebml, EBML_MAX_SIZE_LENGTH, 8);
+            bytes_element(ebml, DOC_TYPE, b"webm");
+            uint_element(ebml, DOC_TYPE_VERSION, 4);
+            uint_element(ebml, DOC_TYPE_READ_VERSION, 2);
+        });
+        write_header(&mut out, SEGMENT, segment_len);
+        out.extend_from_slice(&seek_head);
+        out.extend_from_slice(&info);
+        out.extend_from_slice(&tracks);
+        out.extend_from_slice(&self.clusters);
+        out.extend_from_slice(&cues);
+        Ok(out)
+    }
+}
+
+impl WebmMuxer {
+    fn queue(&mut self, block: QueuedBlock) {
+        match block.track {
+            VIDEO_TRACK => self.video_queue.push_back(block),
+            _ => self.audio_queue.push_back(block),
+        }
+        self.write_queued(false);
+    }
+
+    // Writes queued blocks, earliest first, while the other track can no
+    // longer send anything earlier: it has a later block queued, it is more
+    // than MAX_INTERLEAVE_US behind, or `flush` says nothing more is coming.
+    // Video goes first on a tie, so a keyframe opens the cluster its audio joins.
+    fn write_queued(&mut self, flush: bool) {
+        loop {
+            let video = self.video_queue.front().map(|block| block.timestamp);
+            let audio = self.audio_queue.front().map(|block| block.timestamp);
+            let newest = self.video_queue.back().into_iter()
+                .chain(self.audio_queue.back())
+                .map(|block| block.timestamp)
+                .max();
+            let next = match (video, audio) {
+                (Some(v), Some(a)) => if v <= a { &mut self.video_queue } else { &mut self.audio_queue },
+                (Some(v), None) if flush || self.audio.is_none() || newest.unwrap_or(v).saturating_sub(v) > MAX_INTERLEAVE_US => {
+                    &mut self.video_queue
+                }
+                (None, Some(a)) if flush || newest.unwrap_or(a).saturating_sub(a) > MAX_INTERLEAVE_US => &mut self.audio_queue,
+                _ => return,
+            };
+            let block = next.pop_front().expect("the queue has a front block");
+            self.write_block(block.track, &block.data, block.is_key, block.timestamp);
+        }
+    }
+
+    fn write_block(&mut self, track: u64, data: &[u8], is_key: bool, timestamp: u64) {
+        let mut ticks = timestamp / US_PER_TICK;
+        if let Some(cluster) = self.cluster.as_ref().filter(|cluster| ticks < cluster.timestamp) {
+            // Only a track lagging past MAX_INTERLEAVE_US gets here; a negative
+            // block offset is something many demuxers reject
+            log::warn(&format!(
+                "WebmMuxer: track {} block at {} ms predates its cluster; writing it at {} ms",
+                track, ticks, cluster.timestamp
+            ));
+            ticks = cluster.timestamp;
+        }
+        // Video keyframes open a new, seekable cluster; anything else only moves
+        // on when its offset no longer fits the block's 16 bits
+        let fits = self.cluster.as_ref().is_some_and(|cluster| {
+            i16::try_from(ticks - cluster.timestamp).is_ok()
+        });
+        let keyframe_cluster = track == VIDEO_TRACK && is_key;
+        if keyframe_cluster || !fits {
+            self.close_cluster();
+            if keyframe_cluster {
+                self.cues.push((ticks, self.clusters.len() as u64));
+            }
+            self.cluster = Some(OpenCluster { timestamp: ticks, blocks: Vec::new() });
+        }
+
+        let cluster = self.cluster.as_mut().expect("a cluster was opened above");
+        let relative = (ticks - cluster.timestamp) as i16;
+        // Track number as a 1-byte vint, timestamp offset, flags
+        write_header(&mut cluster.blocks, SIMPLE_BLOCK, data.len() as u64 + 4);
+        cluster.blocks.push(0x80 | track as u8);
+        cluster.blocks.extend_from_slice(&relative.to_be_bytes());
+        cluster.blocks.push(if is_key { 0x80 } else { 0 });
+        cluster.blocks.extend_from_slice(data);
+    }
+
+    fn close_cluster(&mut self) {
+        if let Some(cluster) = self.cluster.take() {
+            element(&mut self.clusters, CLUSTER, |out| {
+                uint_element(out, TIMESTAMP, cluster.timestamp);
+                out.extend_from_slice(&cluster.blocks);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One parsed element: id (with its marker bits, as the constants are),
+    // where its data starts in the file, and the data
+    struct Element<'a> {
+        id: u32,
+        at: usize,
+        data: &'a [u8],
+    }
+
+    // Reads a vint: length from the leading zeros of the first byte
+    fn vint(file: &[u8], at: usize, keep_marker: bool) -> (u64, usize) {
+        let len = file[at].leading_zeros() as usize + 1;
+        let mut value = file[at] as u64;
+        if !keep_marker {
+            value &= (1 << (8 - len)) - 1;
+        }
+        for &byte in &file[at + 1..at + len] {
+            value = (value << 8) | byte as u64;
+        }
+        (value, len)
+    }
+
+    // The elements laid out back to back in `file[start..end]`
+    fn children(file: &[u8], start: usize, end: usize) -> Vec<Element<'_>> {
+        let mut elements = Vec::new();
+        let mut at = start;
+        while at < end {
+            let (id, id_len) = vint(file, at, true);
+            let (size, size_len) = vint(file, at + id_len, false);
+            let data_at = at + id_len + size_len;
+            let data_end = data_at + size as usize;
+            assert!(data_end <= end, "element {:x} runs past its parent", id);
+            elements.push(Element { id: id as u32, at, data: &file[data_at..data_end] });
+            at = data_end;
+        }
+        elements
+    }
+
+    fn data_start(file: &[u8], element: &Element) -> usize {
+        element.data.as_ptr() as usize - file.as_ptr() as usize
+    }
+
+    fn children_of<'a>(file: &'a [u8], parent: &Element<'a>) -> Vec<Element<'a>> {
+        let start = data_start(file, parent);
+        children(file, start, start + parent.data.len())
+    }
+
+    fn child<'a>(file: &'a [u8], parent: &Element<'a>, id: u32) -> Element<'a> {
+        children_of(file, parent).into_iter()
+            .find(|element| element.id == id)
+            .unwrap_or_else(|| panic!("no {:x} in {:x}", id, parent.id))
+    }
+
+    fn uint(data: &[u8]) -> u64 {
+        data.iter().fold(0, |value, &byte| (value << 8) | byte as u64)
+    }
+
+    // 3 s of 25 fps VP9 with keyframes every second, and 3.1 s of Opus
+    fn recording() -> Vec<u8> {
+        let mut muxer = WebmMuxer::new(64, 48, "vp09.00.10.08", &[]).unwrap();
+        muxer.add_audio_track(48_000, 2, &[]).unwrap();
+        for i in 0..155u64 {
+            if i < 75 {
+                muxer.add_frame(&[i as u8; 10], i % 25 == 0, i * 40_000).unwrap();
+            }
+            muxer.add_audio_frame(&[0xfc, i as u8], i * 20_000).unwrap();
+        }
+        muxer.finish().unwrap()
+    }
+
+    #[test]
+    fn writes_a_parseable_file() {
+        let file = recording();
+        let top = children(&file, 0, file.len());
+        assert_eq!(top.iter().map(|element| element.id).collect::<Vec<_>>(), [EBML, SEGMENT]);
+        assert_eq!(child(&file, &top[0], DOC_TYPE).data, b"webm");
+
+        let segment_start = data_start(&file, &top[1]);
+        let level1 = children_of(&file, &top[1]);
+        let ids: Vec<u32> = level1.iter().map(|element| element.id).collect();
+        assert_eq!(ids[..3], [SEEK_HEAD, INFO, TRACKS]);
+        assert_eq!(*ids.last().unwrap(), CUES);
+
+        let info = &level1[1];
+        let duration = f64::from_be_bytes(child(&file, info, DURATION).data.try_into().unwrap());
+        assert_eq!(duration, 3100.0);
+
+        let clusters: Vec<&Element> = level1.iter().filter(|element| element.id == CLUSTER).collect();
+        let timecodes: Vec<u64> = clusters.iter().map(|cluster| uint(child(&file, cluster, TIMESTAMP).data)).collect();
+        assert_eq!(timecodes, [0, 1000, 2000]);
+
+        // Every cue points (relative to the segment data) at the matching cluster
+        let points = children_of(&file, level1.last().unwrap());
+        assert_eq!(points.len(), clusters.len());
+        for (point, cluster) in points.iter().zip(&clusters) {
+            let time = uint(child(&file, point, CUE_TIME).data);
+            let positions = child(&file, point, CUE_TRACK_POSITIONS);
+            let position = uint(child(&file, &positions, CUE_CLUSTER_POSITION).data) as usize;
+            assert_eq!(segment_start + position, cluster.at);
+            assert_eq!(time, uint(child(&file, cluster, TIMESTAMP).data));
+        }
+    }
+
+    // (track, absolute ms) of every SimpleBlock, in file order
+    fn blocks(file: &[u8]) -> Vec<(u64, i64)> {
+        let top = children(file, 0, file.len());
+        let mut blocks = Vec::new();
+        for cluster in children_of(file, &top[1]).iter().filter(|element| element.id == CLUSTER) {
+            let base = uint(child(file, cluster, TIMESTAMP).data) as i64;
+            for block in children_of(file, cluster).iter().filter(|element| element.id == SIMPLE_BLOCK) {
+                let relative = i16::from_be_bytes([block.data[1], block.data[2]]);
+                assert!(relative >= 0, "negative block offset {} in cluster {}", relative, base);
+                blocks.push(((block.data[0] & 0x7F) as u64, base + relative as i64));
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn lagging_audio_is_written_in_timestamp_order() {
+        let mut muxer = WebmMuxer::new(64, 48, "vp8", &[]).unwrap();
+        muxer.add_audio_track(48_000, 1, &[]).unwrap();
+        // Audio is delivered 60 ms behind the video it plays with
+        let mut packets = 0;
+        for i in 0..100u64 {
+            let timestamp = i * 33_333;
+            muxer.add_frame(&[i as u8; 4], i % 30 == 0, timestamp).unwrap();
+            while packets * 20_000 + 60_000 <= timestamp {
+                muxer.add_audio_frame(&[0xfc, packets as u8], packets * 20_000).unwrap();
+                packets += 1;
+            }
+        }
+        let file = muxer.finish().unwrap();
+
+        let blocks = blocks(&file);
+        assert_eq!(blocks.iter().filter(|(track, _)| *track == VIDEO_TRACK).count(), 100);
+        assert_eq!(blocks.iter().filter(|(track, _)| *track == AUDIO_TRACK).count() as u64, packets);
+        assert!(blocks.windows(2).all(|pair| pair[0].1 <= pair[1].1), "{:?}", blocks);
+
+        // Each keyframe still opens the cluster its cue points at
+        let top = children(&file, 0, file.len());
+        let level1 = children_of(&file, &top[1]);
+        let cue_times: Vec<u64> = children_of(&file, level1.last().unwrap()).iter()
+            .map(|point| uint(child(&file, point, CUE_TIME).data))
+            .collect();
+        assert_eq!(cue_times, [0, 999, 1999, 2999]);
+    }
+
+    #[test]
+    fn large_timestamps_do_not_overflow() {
+        let mut muxer = WebmMuxer::new(64, 48, "vp8", &[]).unwrap();
+        let start = u64::MAX / 2;
+        muxer.add_frame(&[1], true, start).unwrap();
+        muxer.add_frame(&[2], false, start + 40_000).unwrap();
+        let blocks = blocks(&muxer.finish().unwrap());
+        assert_eq!(blocks, [(VIDEO_TRACK, (start / 1000) as i64), (VIDEO_TRACK, (start / 1000) as i64 + 40)]);
+    }
+
+    #[test]
+    fn duration_covers_the_last_frame() {
+        let mut muxer = WebmMuxer::new(64, 48, "vp8", &[]).unwrap();
+        for i in 0..10u64 {
+            muxer.add_frame(&[1, 2, 3], i == 0, i * 40_000).unwrap();
+        }
+        let file = muxer.finish().unwrap();
+        let top = children(&file, 0, file.len());
+        let info = child(&file, &top[1], INFO);
+        let duration = f64::from_be_bytes(child(&file, &info, DURATION).data.try_into().unwrap());
+        assert_eq!(duration, 400.0);
+    }
+
+    #[test]
+    fn rejects_bad_starts_and_empty_recordings() {
+        let mut muxer = WebmMuxer::new(64, 48, "vp8", &[]).unwrap();
+        assert!(matches!(muxer.add_frame(&[1], false, 0), Err(MuxError::MissingKeyframe)));
+        assert!(matches!(muxer.finish(), Err(MuxError::NoFrames)));
+        assert!(WebmMuxer::new(64, 48, "av01.0.04M.08", &[]).is_err());
+        assert!(WebmMuxer::new(64, 48, "avc1.42001f", &[]).is_err());
+    }
+}
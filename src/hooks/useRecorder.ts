@@ -321,11 +321,15 @@ export const useRecorder = () => {
                         const buffer = new Uint8Array(chunk.byteLength);
                         chunk.copyTo(buffer);
                         // Muxer uses timescale: 1,000,000 (microseconds) to match VideoFrame
-                        muxerRef.current.add_frame(
-                            buffer,
-                            chunk.type === 'key',
-                            BigInt(chunk.timestamp)
-                        );
+                        try {
+                            muxerRef.current.add_frame(
+                                buffer,
+                                chunk.type === 'key',
+                                BigInt(chunk.timestamp)
+                            );
+                        } catch (e) {
+                            console.error("Muxer failed to write frame:", e);
+                        }
                     }
                 },
                 error: (e) => {